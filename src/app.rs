@@ -1,18 +1,70 @@
-use anyhow::{Result, anyhow, ensure};
+use anyhow::{Context, Result, anyhow, ensure};
 use bevy_math::{IVec3, Vec3};
-use std::path::PathBuf;
+use image::RgbaImage;
+use std::path::{Path, PathBuf};
 
 use crate::{
-    geometry::{GeometryObject, convert_to_geometry, generate_quads},
-    obj::{generate_mtl_file, generate_obj_file},
-    texture::{apply_uv_to_quads, create_texture_file, pack_quad_texture, sort_quads},
+    collider::{ColliderFormat, box_quads, convex_hull, decompose_boxes, format_boxes_json},
+    diff::VoxelDiff,
+    dump::{evaluate_nodes, format_tree},
+    geometry::{
+        Bounds, Geometry, GeometryObject, Neighbourhood, NeighbourhoodKind, Quad,
+        check_repeat_steps, convert_to_geometry, generate_quads, geometry_from_voxels,
+    },
+    lod::{LodRule, downsample, upscale_quads},
+    obj::{
+        Axes, Transform, generate_collider_obj_file, generate_mtl_file, generate_obj_file,
+        generate_polygon_obj_file, generate_triangle_obj_file, generate_welded_obj_file,
+    },
+    preview::{render_preview, render_thumbnail},
+    structured::ModelFile,
+    syntax::{Document, Expr, HalfSpace, Header, Node, Operator, Syntax, format_document},
+    texture::{
+        apply_uv_to_quads, create_colored_texture_file, create_texture_file, pack_quad_texture,
+        sort_quads,
+    },
+    tjunction::{Polygon, count_t_junctions, split_t_junctions},
+    triangulate::{Diagonal, triangulate_polygons, triangulate_quads},
+    validate::{validate_quads, weld_polygons},
 };
 
-pub fn app(file: &PathBuf, output: &PathBuf) -> Result<()> {
+#[derive(Debug, Default)]
+pub struct AppOptions {
+    pub triangulate: Option<Diagonal>,
+    pub fix_t_junctions: bool,
+    pub weld: bool,
+    pub validate: bool,
+    pub dump: bool,
+    pub dump_objs: bool,
+    /// The size of the preview views, if one is rendered.
+    pub preview: Option<u32>,
+    /// The size of the thumbnail to return for the contact sheet, if any.
+    pub thumbnail: Option<u32>,
+    pub axes: Axes,
+    /// The number of levels of detail to write besides the full one.
+    pub lods: u32,
+    pub lod_rule: LodRule,
+    /// The formats to write box colliders in, if any.
+    pub box_colliders: Vec<ColliderFormat>,
+    /// The vertex cap of the convex hull collider, if one is written.
+    pub hull_collider: Option<usize>,
+    pub fill_cavities: bool,
+}
+
+/// Converts the model file and writes the outputs to the output directory.
+/// Returns the thumbnail if one was requested.
+pub fn app(file: &Path, output: &Path, options: &AppOptions) -> Result<Option<RgbaImage>> {
     println!("Processing file: {}", file.display());
-    let content_str = std::fs::read_to_string(file)?;
-    let content = parse_content(content_str)?;
-    let geometry = convert_to_geometry(&content)?;
+    let content = load_content(file)?;
+    if options.dump {
+        dump_tree(&content, file, output, options)?;
+    }
+    let mut geometry = convert_to_geometry(&content)?;
+    if options.fill_cavities {
+        let filled = geometry.fill_cavities();
+        println!("Filled {} voxels of enclosed cavities", filled);
+    }
+    let transform = Transform::new(&content, options.axes);
 
     let mut quads = generate_quads(&geometry);
     sort_quads(&mut quads);
@@ -27,117 +79,811 @@ pub fn app(file: &PathBuf, output: &PathBuf) -> Result<()> {
     let texture = create_texture_file(&tex_quads, size);
     texture.save(&texture_path)?;
 
-    let obj_file = generate_obj_file(geometry.resolution, content.origin, quads, &mtl_path);
+    if options.validate {
+        let report = validate_quads(&quads, options.fix_t_junctions, options.weld);
+        println!("{}", report);
+        ensure!(report.is_valid(), "Invalid mesh for {}", file.display());
+    }
+
+    let stem = file.file_stem().unwrap().to_string_lossy();
+    if let Some(tile_size) = options.preview {
+        let preview = render_preview(&quads, &texture, &transform, tile_size);
+        preview.save(output.join(format!("{}_preview.png", stem)))?;
+    }
+    let thumbnail = options
+        .thumbnail
+        .map(|size| render_thumbnail(&quads, &texture, &transform, size));
+
+    if let Some(max_vertices) = options.hull_collider {
+        let triangles = convex_hull(&quads, max_vertices)?;
+        println!("Convex hull collider with {} triangles", triangles.len());
+        let hull_path = output.join(format!("{}_hull.obj", stem));
+        let hull_file = generate_collider_obj_file(&transform, triangles);
+        std::fs::write(hull_path, hull_file)?;
+    }
+
+    let obj_file = match (options.fix_t_junctions, options.triangulate) {
+        (false, None) => generate_obj_file(&transform, quads, &mtl_path),
+        (false, Some(diagonal)) => {
+            let triangles = triangulate_quads(&quads, &geometry, diagonal);
+            generate_triangle_obj_file(&transform, triangles, &mtl_path)
+        }
+        (true, triangulate) => {
+            let unsplit: Vec<_> = quads.iter().map(Polygon::from).collect();
+            println!("Splitting {} T-junctions", count_t_junctions(&unsplit));
+            let polygons = split_t_junctions(&quads);
+            if options.weld {
+                let mesh = weld_polygons(&polygons, true);
+                generate_welded_obj_file(&transform, mesh, &mtl_path)
+            } else if triangulate.is_some() {
+                let triangles = triangulate_polygons(&polygons);
+                generate_triangle_obj_file(&transform, triangles, &mtl_path)
+            } else {
+                generate_polygon_obj_file(&transform, polygons, &mtl_path)
+            }
+        }
+    };
     std::fs::write(obj_path, obj_file)?;
 
     let mtl_file = generate_mtl_file(&texture_path);
     std::fs::write(mtl_path, mtl_file)?;
+
+    if !options.box_colliders.is_empty() {
+        write_box_colliders(&geometry, file, output, &transform, &options.box_colliders)?;
+    }
+    if options.lods > 0 {
+        write_lods(&geometry, file, output, &transform, options)?;
+    }
+    Ok(thumbnail)
+}
+
+/// Decomposes the voxels into boxes and writes them as collision shapes in
+/// every given format.
+fn write_box_colliders(
+    geometry: &Geometry,
+    file: &Path,
+    output: &Path,
+    transform: &Transform,
+    formats: &[ColliderFormat],
+) -> Result<()> {
+    let stem = file.file_stem().unwrap().to_string_lossy();
+    let boxes = decompose_boxes(geometry);
+    println!("Decomposed into {} collision boxes", boxes.len());
+    for format in formats {
+        match format {
+            ColliderFormat::Json => std::fs::write(
+                output.join(format!("{}_boxes.json", stem)),
+                format_boxes_json(&boxes, transform),
+            )?,
+            ColliderFormat::Obj => {
+                let obj_path = output.join(format!("{}_collision.obj", stem));
+                write_quad_mesh(box_quads(&boxes), transform, &obj_path)?;
+            }
+        }
+    }
     Ok(())
 }
 
-fn parse_content(content: String) -> Result<FileContent> {
-    let lines = content
-        .lines()
-        .filter(|l| !l.is_empty())
-        .collect::<Vec<_>>();
-    let header_line = lines[0];
+/// Writes `<name>_lod0.obj` at full detail and every further level
+/// downsampled from the one before, at half its resolution, stopping early if
+/// no voxels are left.
+fn write_lods(
+    geometry: &Geometry,
+    file: &Path,
+    output: &Path,
+    transform: &Transform,
+    options: &AppOptions,
+) -> Result<()> {
+    let stem = file.file_stem().unwrap().to_string_lossy();
+    let mut voxels = geometry.solid_voxels();
+    for level in 0..=options.lods {
+        if level > 0 {
+            voxels = downsample(&voxels, 2, options.lod_rule);
+        }
+        if voxels.is_empty() {
+            println!("No voxels left at LOD {}, skipping the rest", level);
+            break;
+        }
+        let mut quads = generate_quads(&geometry_from_voxels(&voxels)?);
+        upscale_quads(&mut quads, 1 << level);
+        let obj_path = output.join(format!("{}_lod{}.obj", stem, level));
+        write_quad_mesh(quads, transform, &obj_path)?;
+    }
+    Ok(())
+}
+
+/// Writes the geometry tree with the voxel count and bounds of every node,
+/// and optionally the voxels of every node as its own mesh.
+fn dump_tree(
+    content: &FileContent,
+    file: &Path,
+    output: &Path,
+    options: &AppOptions,
+) -> Result<()> {
+    let stem = file.file_stem().unwrap().to_string_lossy();
+    let nodes = evaluate_nodes(&content.obj);
+    std::fs::write(
+        output.join(format!("{}_tree.txt", stem)),
+        format_tree(&nodes),
+    )?;
+    if options.dump_objs {
+        let transform = Transform::new(content, options.axes);
+        let nodes_dir = output.join(format!("{}_nodes", stem));
+        std::fs::create_dir_all(&nodes_dir)?;
+        for (i, node) in nodes.iter().enumerate() {
+            if node.voxels.is_empty() {
+                continue;
+            }
+            let geometry = geometry_from_voxels(&node.voxels)?;
+            let obj_path = nodes_dir.join(format!("node_{}.obj", i));
+            write_quad_mesh(generate_quads(&geometry), &transform, &obj_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the quads with their texture, next to the OBJ.
+fn write_quad_mesh(mut quads: Vec<Quad>, transform: &Transform, obj_path: &Path) -> Result<()> {
+    sort_quads(&mut quads);
+    let (tex_quads, size) = pack_quad_texture(&quads);
+    apply_uv_to_quads(&mut quads, &tex_quads, size);
+
+    let texture_path = obj_path.with_extension("png");
+    let mtl_path = obj_path.with_extension("mtl");
+    create_texture_file(&tex_quads, size).save(&texture_path)?;
+    let obj_file = generate_obj_file(transform, quads, &mtl_path);
+    std::fs::write(obj_path, obj_file)?;
+    std::fs::write(mtl_path, generate_mtl_file(&texture_path))?;
+    Ok(())
+}
+
+/// Compares the voxels of an earlier version of a model with the model file
+/// and writes them as `<name>_diff.obj`, colored by whether they were added,
+/// removed or kept, and optionally renders a preview of it.
+pub fn diff_files(
+    old: &FileContent,
+    after: &Path,
+    output: &Path,
+    axes: Axes,
+    preview: Option<u32>,
+) -> Result<VoxelDiff> {
+    let new = load_content(after)?;
+    ensure!(
+        old.resolution == new.resolution,
+        "Resolutions differ: {} and {}",
+        old.resolution,
+        new.resolution
+    );
+    let diff = VoxelDiff::new(&old.obj.get_voxels(), &new.obj.get_voxels());
+    let transform = Transform::new(&new, axes);
+    let (mut quads, colors) = diff.colored_quads()?;
+    let (tex_quads, size) = pack_quad_texture(&quads);
+    apply_uv_to_quads(&mut quads, &tex_quads, size);
+    let texture = create_colored_texture_file(&tex_quads, size, &colors);
+
+    let stem = after.file_stem().unwrap().to_string_lossy();
+    if let Some(tile_size) = preview {
+        let preview = render_preview(&quads, &texture, &transform, tile_size);
+        preview.save(output.join(format!("{}_diff_preview.png", stem)))?;
+    }
+    let obj_path = output.join(format!("{}_diff.obj", stem));
+    let texture_path = obj_path.with_extension("png");
+    let mtl_path = obj_path.with_extension("mtl");
+    texture.save(&texture_path)?;
+    let obj_file = generate_obj_file(&transform, quads, &mtl_path);
+    std::fs::write(obj_path, obj_file)?;
+    std::fs::write(mtl_path, generate_mtl_file(&texture_path))?;
+    Ok(diff)
+}
+
+/// Rewrites a model file in canonical form, or with `check` only tells
+/// whether it already is. Returns whether the file was formatted.
+pub fn format_file(file: &Path, check: bool) -> Result<bool> {
+    let content = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to open {}", file.display()))?;
+    let document = parse_document(&content).with_context(|| format!("In {}", file.display()))?;
+    let formatted = format_document(&document);
+    if formatted == content {
+        return Ok(true);
+    }
+    if !check {
+        std::fs::write(file, formatted)?;
+    }
+    Ok(false)
+}
+
+/// Tracks the files being loaded, to resolve relative includes and detect
+/// include cycles.
+#[derive(Debug, Default)]
+struct Includes {
+    stack: Vec<PathBuf>,
+    resolution: i32,
+}
+
+/// The state of parsing a single file: its syntax, and the comments read
+/// since the last expression took them.
+#[derive(Debug, Default)]
+struct ParseState {
+    syntax: Syntax,
+    comments: Vec<Comment>,
+}
+
+#[derive(Debug)]
+struct Comment {
+    text: String,
+    /// Whether code precedes the comment on its line.
+    inline: bool,
+}
+
+impl ParseState {
+    fn take_comments(&mut self) -> Vec<String> {
+        self.comments.drain(..).map(|c| c.text).collect()
+    }
+
+    /// Skips to the next token and returns the comments at the end of the
+    /// current line, leaving comments on later lines to the next expression.
+    fn take_trailing(&mut self, content: &[char], index: &mut usize) -> Option<String> {
+        skip_space(content, index, self);
+        let (inline, own_line) = self.comments.drain(..).partition(|c: &Comment| c.inline);
+        self.comments = own_line;
+        let inline = inline.into_iter().map(|c| c.text).collect::<Vec<_>>();
+        (!inline.is_empty()).then(|| inline.join(" "))
+    }
+}
+
+pub fn load_content(file: &Path) -> Result<FileContent> {
+    load_included(file, &mut Includes::default())
+}
+
+/// Loads the model in `source` as if it were at `file`, resolving its
+/// includes from the directory of `file`.
+pub fn load_content_as(source: &Path, file: &Path) -> Result<FileContent> {
+    let content = std::fs::read_to_string(source)
+        .with_context(|| format!("Failed to open {}", source.display()))?;
+    let path = file
+        .canonicalize()
+        .with_context(|| format!("Failed to open {}", file.display()))?;
+    let mut includes = Includes {
+        stack: vec![path.clone()],
+        ..Default::default()
+    };
+    parse_file(&path, content, &mut includes).with_context(|| format!("In {}", source.display()))
+}
+
+fn load_included(file: &Path, includes: &mut Includes) -> Result<FileContent> {
+    let path = file
+        .canonicalize()
+        .with_context(|| format!("Failed to open {}", file.display()))?;
+    if let Some(i) = includes.stack.iter().position(|p| *p == path) {
+        let cycle = includes.stack[i..]
+            .iter()
+            .chain([&path])
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>();
+        return Err(anyhow!("Include cycle: {}", cycle.join(" -> ")));
+    }
+    let content_str = std::fs::read_to_string(&path)?;
+    includes.stack.push(path.clone());
+    let content = parse_file(&path, content_str, includes);
+    includes.stack.pop();
+    content.with_context(|| format!("In {}", path.display()))
+}
+
+/// Parses a file as JSON or RON if its extension says so, and in the text
+/// format otherwise.
+fn parse_file(path: &Path, content: String, includes: &mut Includes) -> Result<FileContent> {
+    let model: ModelFile = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&content)?,
+        Some("ron") => ron::from_str(&content)?,
+        _ => return parse_content(content, includes),
+    };
+    lower_document(model.into_document()?, includes)
+}
+
+fn parse_content(content: String, includes: &mut Includes) -> Result<FileContent> {
+    lower_document(parse_document(&content)?, includes)
+}
+
+fn lower_document(document: Document, includes: &mut Includes) -> Result<FileContent> {
+    let resolution = document.header.resolution;
+    let parent_resolution = std::mem::replace(&mut includes.resolution, resolution);
+    let obj = lower(document.expr, includes);
+    includes.resolution = parent_resolution;
+    Ok(FileContent {
+        resolution,
+        origin: document.header.origin,
+        scale: document.header.scale,
+        unit: document.header.unit,
+        obj: obj?,
+    })
+}
+
+/// Parses a model file into its syntax tree, keeping its comments.
+pub fn parse_document(content: &str) -> Result<Document> {
+    // `lines` also strips the `\r` of CRLF line endings.
+    let mut lines = content.lines();
+    let mut header_comments = Vec::new();
+    let (header_line, header_comment) = loop {
+        let line = lines.next().ok_or(anyhow!("Empty file"))?;
+        let (code, comment) = split_comment(line);
+        if !code.trim().is_empty() {
+            break (code, comment);
+        }
+        header_comments.extend(comment.map(str::to_string));
+    };
     let header_parts = header_line
         .split(";")
-        .filter(|s| !s.is_empty())
+        .filter(|s| !s.trim().is_empty())
         .collect::<Vec<_>>();
     ensure!(
-        header_parts.len() == 2,
-        "Expected 2 parts, got {}",
+        header_parts.len() >= 2,
+        "Expected at least 2 parts, got {}",
         header_parts.len()
     );
-    let resolution = header_parts[0].parse::<i32>()?;
+    let resolution = header_parts[0].trim().parse::<i32>()?;
     ensure!(resolution > 0, "Invalid resolution");
 
     let origin = parse_vec3(header_parts[1].to_string())?;
+    let (mut scale, mut unit, mut syntax) = (Vec3::ONE, 1.0, Syntax::Prefix);
+    for part in &header_parts[2..] {
+        let part = part.trim();
+        let (keyword, value) = part.split_once(char::is_whitespace).unwrap_or((part, ""));
+        match keyword {
+            "scale" => {
+                scale = parse_vec3(value.to_string()).context("Invalid scale")?;
+                ensure!(
+                    scale.min_element() > 0.0 && scale.is_finite(),
+                    "Invalid scale"
+                );
+            }
+            "unit" => {
+                unit = value.trim().parse::<f32>().context("Invalid unit")?;
+                ensure!(unit > 0.0 && unit.is_finite(), "Invalid unit");
+            }
+            "v2" if value.is_empty() => syntax = Syntax::Infix,
+            _ if keyword.starts_with('v') && value.is_empty() => {
+                return Err(anyhow!("Unknown version: {}", part));
+            }
+            _ => return Err(anyhow!("Unknown header part: {}", part)),
+        }
+    }
 
-    let other_lines = lines.iter().skip(1);
-    let other_str = other_lines
-        .map(|s| s.to_string())
-        .collect::<Vec<String>>()
-        .join("\n");
+    let other_str = lines.collect::<Vec<_>>().join("\n");
+    let chars = other_str.chars().collect::<Vec<_>>();
     let mut index = 0;
-    let obj = parse_geometry(&other_str.chars().collect::<Vec<_>>(), &mut index)?;
-    if index != other_str.len() {
+    let mut state = ParseState {
+        syntax,
+        comments: Vec::new(),
+    };
+    let expr = match syntax {
+        Syntax::Prefix => parse_geometry(&chars, &mut index, &mut state)?,
+        Syntax::Infix => parse_infix(&chars, &mut index, &mut state)?,
+    };
+    if index != chars.len() {
         return Err(anyhow!("Found extra characters at the end of the file"));
     }
-    Ok(FileContent {
-        resolution,
-        origin,
-        obj,
+    Ok(Document {
+        header: Header {
+            comments: header_comments,
+            trailing: header_comment.map(str::to_string),
+            resolution,
+            origin,
+            scale,
+            unit,
+            syntax,
+        },
+        expr,
+        comments: state.take_comments(),
     })
 }
 
-fn parse_geometry(content: &[char], index: &mut usize) -> Result<GeometryObject> {
-    while content.get(*index).is_some_and(|c| c.is_whitespace()) {
-        *index += 1;
-    }
+/// Converts a syntax tree into geometry, loading the files it includes.
+fn lower(expr: Expr, includes: &mut Includes) -> Result<GeometryObject> {
+    Ok(match expr.node {
+        Node::Cube(cube) => GeometryObject::Cube(cube),
+        Node::Include(file) => parse_include(&file, includes)?,
+        Node::Binary(operator, left, right) => {
+            let left = lower(*left, includes)?;
+            let right = lower(*right, includes)?;
+            combine(operator, left, right)
+        }
+        Node::Unary(operator, operand) => {
+            let obj = Box::new(lower(*operand, includes)?);
+            match operator {
+                Operator::Wireframe(neighbourhood) => GeometryObject::Wireframe(obj, neighbourhood),
+                Operator::Hull(neighbourhood) => GeometryObject::Hull(obj, neighbourhood),
+                Operator::Grow(neighbourhood) => GeometryObject::Grow(obj, neighbourhood),
+                Operator::Shrink(neighbourhood) => GeometryObject::Shrink(obj, neighbourhood),
+                Operator::Open(neighbourhood) => GeometryObject::Open(obj, neighbourhood),
+                Operator::Close(neighbourhood) => GeometryObject::Close(obj, neighbourhood),
+                Operator::Shell(neighbourhood, open) => {
+                    GeometryObject::Shell(obj, neighbourhood, open)
+                }
+                Operator::Repeat(steps) => GeometryObject::Repeat(obj, steps),
+                Operator::Crop(bounds) => GeometryObject::Crop(obj, bounds),
+                Operator::Cut(half_space) => GeometryObject::Crop(obj, half_space.bounds()),
+            }
+        }
+    })
+}
+
+fn parse_geometry(content: &[char], index: &mut usize, state: &mut ParseState) -> Result<Expr> {
+    skip_space(content, index, state);
+    let comments = state.take_comments();
     let c = content
         .get(*index)
         .ok_or(anyhow!("Unexpected end of file"))?;
     *index += 1;
-    let res = match c {
+    let (trailing, node) = match c {
         '(' => {
-            let mut i = *index;
-            while content.get(i).is_some_and(|c| *c != ')') {
-                i += 1;
-            }
-            if content.get(i).is_none() {
-                return Err(anyhow!("Expected ')'"));
+            let cube = parse_cube(&read_group(content, index, state)?)?;
+            (state.take_trailing(content, index), Node::Cube(cube))
+        }
+        '&' | '+' | '-' | '/' if state.syntax == Syntax::Prefix => {
+            let trailing = state.take_trailing(content, index);
+            let left = parse_geometry(content, index, state)?;
+            let right = parse_geometry(content, index, state)?;
+            (trailing, Node::Binary(*c, Box::new(left), Box::new(right)))
+        }
+        'i' => {
+            let keyword = "include";
+            let rest = content[*index - 1..]
+                .iter()
+                .take(keyword.len())
+                .collect::<String>();
+            ensure!(rest == keyword, "Unexpected character: {}", c);
+            *index += keyword.len() - 1;
+            while content.get(*index).is_some_and(|c| c.is_whitespace()) {
+                *index += 1;
             }
-            let str = content[*index..i].iter().collect::<String>();
-            *index = i + 1;
+            ensure!(
+                content.get(*index) == Some(&'"'),
+                "Expected '\"' after include"
+            );
+            let end = (*index + 1..content.len())
+                .find(|&i| content[i] == '"')
+                .ok_or(anyhow!("Expected '\"'"))?;
+            let file = content[*index + 1..end].iter().collect::<String>();
+            *index = end + 1;
+            (state.take_trailing(content, index), Node::Include(file))
+        }
+        _ => {
+            let operator = parse_operator(*c, content, index, state)?;
+            let trailing = state.take_trailing(content, index);
+            let operand = parse_operand(content, index, state)?;
+            (trailing, Node::Unary(operator, Box::new(operand)))
+        }
+    };
+    Ok(Expr {
+        comments,
+        trailing,
+        node,
+    })
+}
+
+fn parse_operator(
+    c: char,
+    content: &[char],
+    index: &mut usize,
+    state: &mut ParseState,
+) -> Result<Operator> {
+    Ok(match c {
+        'w' => Operator::Wireframe(parse_neighbourhood(content, index, state)?),
+        'h' => Operator::Hull(parse_neighbourhood(content, index, state)?),
+        'g' => Operator::Grow(parse_neighbourhood(content, index, state)?),
+        's' => Operator::Shrink(parse_neighbourhood(content, index, state)?),
+        'o' => Operator::Open(parse_neighbourhood(content, index, state)?),
+        'c' => Operator::Close(parse_neighbourhood(content, index, state)?),
+        'e' => {
+            let mut params = parse_params(content, index, state)?;
+            let open = match params.iter().position(|(key, _)| key == "open") {
+                Some(i) => Some(parse_direction(&params.remove(i).1)?),
+                None => None,
+            };
+            Operator::Shell(get_neighbourhood(&params)?, open)
+        }
+        'b' => {
+            let str = parse_group(content, index, state, 'b')?;
             let cube = parse_cube(&str)?;
-            Ok(GeometryObject::Cube(cube))
-        }
-        '&' => {
-            let left = Box::new(parse_geometry(content, index)?);
-            let right = Box::new(parse_geometry(content, index)?);
-            Ok(GeometryObject::Intersection(left, right))
-        }
-        '+' => {
-            let left = Box::new(parse_geometry(content, index)?);
-            let right = Box::new(parse_geometry(content, index)?);
-            Ok(GeometryObject::Union(left, right))
-        }
-        '-' => {
-            let left = Box::new(parse_geometry(content, index)?);
-            let right = Box::new(parse_geometry(content, index)?);
-            Ok(GeometryObject::Minus(left, right))
-        }
-        '/' => {
-            let left = Box::new(parse_geometry(content, index)?);
-            let right = Box::new(parse_geometry(content, index)?);
-            Ok(GeometryObject::SymmetricDifference(left, right))
-        }
-        'w' => {
-            let obj = Box::new(parse_geometry(content, index)?);
-            Ok(GeometryObject::Wireframe(obj))
-        }
-        'h' => {
-            let obj = Box::new(parse_geometry(content, index)?);
-            Ok(GeometryObject::Hull(obj))
-        }
-        'g' => {
-            let obj = Box::new(parse_geometry(content, index)?);
-            Ok(GeometryObject::Grow(obj))
-        }
-        _ => Err(anyhow!("Unexpected character: {}", c)),
-    }?;
-    while content.get(*index).is_some_and(|c| c.is_whitespace()) {
+            let mut max = cube.corner;
+            for axis in 0..3 {
+                max[axis] = max[axis]
+                    .checked_add(cube.size[axis])
+                    .ok_or_else(|| anyhow!("Crop bounds overflow: {}", str.trim()))?;
+            }
+            Operator::Crop(Bounds {
+                min: cube.corner,
+                max,
+            })
+        }
+        't' => {
+            let str = parse_group(content, index, state, 't')?;
+            Operator::Cut(parse_half_space(&str)?)
+        }
+        'r' => {
+            let str = parse_group(content, index, state, 'r')?;
+            Operator::Repeat(parse_repeat(&str)?)
+        }
+        _ => return Err(anyhow!("Unexpected character: {}", c)),
+    })
+}
+
+/// Parses an infix expression. `&` binds tighter than `+`, `-` and `/`, and
+/// operators of the same precedence are applied from left to right, so that
+/// `A + B + C` unions any number of operands. Unary operators apply to the
+/// operand directly following them.
+fn parse_infix(content: &[char], index: &mut usize, state: &mut ParseState) -> Result<Expr> {
+    let mut left = parse_infix_term(content, index, state)?;
+    while let Some(&c) = content.get(*index).filter(|c| matches!(c, '+' | '-' | '/')) {
+        *index += 1;
+        let right = parse_infix_term(content, index, state)?;
+        left = Expr::binary(c, left, right);
+    }
+    Ok(left)
+}
+
+fn parse_infix_term(content: &[char], index: &mut usize, state: &mut ParseState) -> Result<Expr> {
+    let mut left = parse_operand(content, index, state)?;
+    while content.get(*index) == Some(&'&') {
         *index += 1;
+        let right = parse_operand(content, index, state)?;
+        left = Expr::binary('&', left, right);
+    }
+    Ok(left)
+}
+
+/// Parses the operand of an operator, which in infix syntax may also be a
+/// parenthesized expression.
+fn parse_operand(content: &[char], index: &mut usize, state: &mut ParseState) -> Result<Expr> {
+    skip_space(content, index, state);
+    if state.syntax == Syntax::Prefix
+        || content.get(*index) != Some(&'(')
+        || is_cube(content, *index)
+    {
+        return parse_geometry(content, index, state);
+    }
+    *index += 1;
+    let mut expr = parse_infix(content, index, state)?;
+    ensure!(content.get(*index) == Some(&')'), "Expected ')'");
+    *index += 1;
+    if let Some(comment) = state.take_trailing(content, index) {
+        expr.trailing = Some(match expr.trailing {
+            Some(trailing) => format!("{} {}", trailing, comment),
+            None => comment,
+        });
     }
-    Ok(res)
+    Ok(expr)
+}
+
+/// Tells a cube apart from a parenthesized expression, which contains
+/// operators or nested parentheses.
+fn is_cube(content: &[char], start: usize) -> bool {
+    let mut index = start + 1;
+    read_group(content, &mut index, &mut ParseState::default()).is_ok_and(|str| {
+        str.chars()
+            .all(|c| c.is_ascii_digit() || c.is_whitespace() || matches!(c, '-' | ';'))
+    })
+}
+
+fn combine(operator: char, left: GeometryObject, right: GeometryObject) -> GeometryObject {
+    let (left, right) = (Box::new(left), Box::new(right));
+    match operator {
+        '&' => GeometryObject::Intersection(left, right),
+        '+' => GeometryObject::Union(left, right),
+        '-' => GeometryObject::Minus(left, right),
+        '/' => GeometryObject::SymmetricDifference(left, right),
+        _ => unreachable!("Unknown binary operator: {}", operator),
+    }
+}
+
+/// A `#` or `//` comment runs to the end of its line. In prefix syntax `//`
+/// also nests two symmetric differences, as in `//(A)(B)(C)`, so there it
+/// only starts a comment when followed by whitespace or the end of the line.
+fn is_comment(content: &[char], index: usize, syntax: Syntax) -> bool {
+    match content[index..] {
+        ['#', ..] => true,
+        ['/', '/', c, ..] if syntax == Syntax::Prefix => c.is_whitespace(),
+        ['/', '/', ..] => true,
+        _ => false,
+    }
+}
+
+/// Skips whitespace and comments, collecting the comments.
+fn skip_space(content: &[char], index: &mut usize, state: &mut ParseState) {
+    loop {
+        while content.get(*index).is_some_and(|c| c.is_whitespace()) {
+            *index += 1;
+        }
+        if !is_comment(content, *index, state.syntax) {
+            return;
+        }
+        read_comment(content, index, state);
+    }
+}
+
+fn read_comment(content: &[char], index: &mut usize, state: &mut ParseState) {
+    let line_start = content[..*index]
+        .iter()
+        .rposition(|c| *c == '\n')
+        .map_or(0, |i| i + 1);
+    let end = (*index..content.len())
+        .find(|&i| content[i] == '\n')
+        .unwrap_or(content.len());
+    state.comments.push(Comment {
+        text: content[*index..end]
+            .iter()
+            .collect::<String>()
+            .trim_end()
+            .to_string(),
+        inline: content[line_start..*index]
+            .iter()
+            .any(|c| !c.is_whitespace()),
+    });
+    *index = end;
+}
+
+/// Reads the text after an opening `(` up to the closing `)`, collecting the
+/// comments in it.
+fn read_group(content: &[char], index: &mut usize, state: &mut ParseState) -> Result<String> {
+    let mut str = String::new();
+    loop {
+        match content.get(*index) {
+            None => return Err(anyhow!("Expected ')'")),
+            Some(')') => break,
+            Some(_) if is_comment(content, *index, state.syntax) => {
+                read_comment(content, index, state)
+            }
+            Some(c) => {
+                str.push(*c);
+                *index += 1;
+            }
+        }
+    }
+    *index += 1;
+    Ok(str)
+}
+
+/// Splits a line into its code and its `#` or `//` comment.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    match line.find('#').into_iter().chain(line.find("//")).min() {
+        Some(i) => (&line[..i], Some(line[i..].trim_end())),
+        None => (line, None),
+    }
+}
+
+/// Loads an included file relative to the including one. Its origin, scale
+/// and unit are ignored, and its voxels are scaled up if its resolution
+/// divides ours.
+fn parse_include(file: &str, includes: &mut Includes) -> Result<GeometryObject> {
+    let dir = includes
+        .stack
+        .last()
+        .and_then(|p| p.parent())
+        .unwrap_or(Path::new("."));
+    let resolution = includes.resolution;
+    let included = load_included(&dir.join(file), includes)?;
+    ensure!(
+        resolution % included.resolution == 0,
+        "Resolution {} of {} does not divide resolution {}",
+        included.resolution,
+        file,
+        resolution
+    );
+    let factor = resolution / included.resolution;
+    if factor == 1 {
+        Ok(included.obj)
+    } else {
+        Ok(GeometryObject::Scale(Box::new(included.obj), factor))
+    }
+}
+
+/// Parses the optional parameters of an operator, given either as a radius
+/// directly after the operator (`g3`) or as a parameter list (`g(r=2, n=6)`).
+fn parse_params(
+    content: &[char],
+    index: &mut usize,
+    state: &mut ParseState,
+) -> Result<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    let start = *index;
+    while content.get(*index).is_some_and(|c| c.is_ascii_digit()) {
+        *index += 1;
+    }
+    if *index > start {
+        let radius = content[start..*index].iter().collect::<String>();
+        params.push(("r".to_string(), radius));
+    }
+
+    // A parameter list is told apart from a cube by its `=` signs.
+    let mut i = *index;
+    while content.get(i).is_some_and(|c| c.is_whitespace()) {
+        i += 1;
+    }
+    if content.get(i) == Some(&'(') {
+        let mut end = i + 1;
+        let mut group_state = ParseState::default();
+        let str = read_group(content, &mut end, &mut group_state)?;
+        if str.contains('=') {
+            *index = end;
+            state.comments.append(&mut group_state.comments);
+            for param in str.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let (key, value) = param
+                    .split_once('=')
+                    .ok_or(anyhow!("Expected key=value, got {}", param))?;
+                params.push((key.trim().to_string(), value.trim().to_string()));
+            }
+        }
+    }
+    Ok(params)
+}
+
+fn parse_neighbourhood(
+    content: &[char],
+    index: &mut usize,
+    state: &mut ParseState,
+) -> Result<Neighbourhood> {
+    let params = parse_params(content, index, state)?;
+    get_neighbourhood(&params)
+}
+
+fn get_neighbourhood(params: &[(String, String)]) -> Result<Neighbourhood> {
+    let mut neighbourhood = Neighbourhood::default();
+    for (key, value) in params {
+        match key.as_str() {
+            "r" => neighbourhood.radius = value.parse()?,
+            "n" => {
+                neighbourhood.kind = match value.as_str() {
+                    "6" => NeighbourhoodKind::Faces,
+                    "18" => NeighbourhoodKind::Edges,
+                    "26" => NeighbourhoodKind::Corners,
+                    "s" | "sphere" => NeighbourhoodKind::Sphere,
+                    _ => return Err(anyhow!("Invalid neighbourhood: {}", value)),
+                }
+            }
+            "axes" => {
+                neighbourhood.axes = IVec3::ZERO;
+                for axis in value.chars() {
+                    match axis {
+                        'x' => neighbourhood.axes.x = 1,
+                        'y' => neighbourhood.axes.y = 1,
+                        'z' => neighbourhood.axes.z = 1,
+                        _ => return Err(anyhow!("Invalid axis: {}", axis)),
+                    }
+                }
+            }
+            _ => return Err(anyhow!("Unknown parameter: {}", key)),
+        }
+    }
+    ensure!(neighbourhood.radius > 0, "Invalid radius");
+    ensure!(
+        neighbourhood.radius <= Neighbourhood::MAX_RADIUS,
+        "Radius {} is too large, the largest is {}",
+        neighbourhood.radius,
+        Neighbourhood::MAX_RADIUS
+    );
+    ensure!(neighbourhood.axes != IVec3::ZERO, "No axes given");
+    Ok(neighbourhood)
+}
+
+fn parse_direction(str: &str) -> Result<IVec3> {
+    let (sign, axis) = match str.split_at_checked(1) {
+        Some(("-", axis)) => (-1, axis),
+        Some(("+", axis)) => (1, axis),
+        _ => (1, str),
+    };
+    let dir = match axis {
+        "x" => IVec3::X,
+        "y" => IVec3::Y,
+        "z" => IVec3::Z,
+        _ => return Err(anyhow!("Invalid direction: {}", str)),
+    };
+    Ok(sign * dir)
 }
 
 fn parse_cube(line: &str) -> Result<Cube> {
     let parts = line
         .split(";")
-        .filter(|s| !s.is_empty())
+        .filter(|s| !s.trim().is_empty())
         .collect::<Vec<_>>();
     ensure!(parts.len() == 2, "Expected 2 parts, got {}", parts.len());
     let corner = parse_ivec3(parts[0].to_string())?;
@@ -146,6 +892,72 @@ fn parse_cube(line: &str) -> Result<Cube> {
     Ok(Cube { corner, size })
 }
 
+/// Parses the mandatory parenthesized arguments of an operator.
+fn parse_group(
+    content: &[char],
+    index: &mut usize,
+    state: &mut ParseState,
+    operator: char,
+) -> Result<String> {
+    while content.get(*index).is_some_and(|c| c.is_whitespace()) {
+        *index += 1;
+    }
+    ensure!(
+        content.get(*index) == Some(&'('),
+        "Expected '(' after '{}'",
+        operator
+    );
+    *index += 1;
+    read_group(content, index, state)
+}
+
+/// Parses a half-space, as in `x < 8` or `y >= -2`.
+fn parse_half_space(str: &str) -> Result<HalfSpace> {
+    let parts = str.split_whitespace().collect::<Vec<_>>();
+    ensure!(parts.len() == 3, "Expected 3 parts, got {}", parts.len());
+    let axis = match parts[0] {
+        "x" => 0,
+        "y" => 1,
+        "z" => 2,
+        _ => return Err(anyhow!("Invalid axis: {}", parts[0])),
+    };
+    let value = parts[2].parse::<i32>()?;
+    let next = || {
+        value
+            .checked_add(1)
+            .ok_or(anyhow!("Value out of range: {}", value))
+    };
+    let (above, value) = match parts[1] {
+        "<" => (false, value),
+        "<=" => (false, next()?),
+        ">" => (true, next()?),
+        ">=" => (true, value),
+        _ => return Err(anyhow!("Invalid comparison: {}", parts[1])),
+    };
+    Ok(HalfSpace { axis, above, value })
+}
+
+/// Parses pairs of steps and counts, as in `4 0 0; 3; 0 0 4; 2`.
+fn parse_repeat(str: &str) -> Result<Vec<(IVec3, i32)>> {
+    let parts = str
+        .split(";")
+        .filter(|s| !s.trim().is_empty())
+        .collect::<Vec<_>>();
+    ensure!(
+        !parts.is_empty() && parts.len() % 2 == 0,
+        "Expected pairs of step and count, got {} parts",
+        parts.len()
+    );
+    let mut steps = Vec::new();
+    for pair in parts.chunks(2) {
+        let step = parse_ivec3(pair[0].to_string())?;
+        let count = pair[1].trim().parse::<i32>()?;
+        steps.push((step, count));
+    }
+    check_repeat_steps(&steps)?;
+    Ok(steps)
+}
+
 fn parse_ivec3(str: String) -> Result<IVec3> {
     let parts = str
         .split_whitespace()
@@ -163,7 +975,8 @@ fn parse_vec3(str: String) -> Result<Vec3> {
     ensure!(parts.len() == 3, "Expected 3 parts, got {}", parts.len());
     Ok(Vec3::new(parts[0], parts[1], parts[2]))
 }
-#[derive(Debug)]
+
+#[derive(Debug, Clone)]
 pub struct Cube {
     pub corner: IVec3,
     pub size: IVec3,
@@ -173,5 +986,553 @@ pub struct Cube {
 pub struct FileContent {
     pub resolution: i32,
     pub origin: Vec3,
+    pub scale: Vec3,
+    pub unit: f32,
     pub obj: GeometryObject,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn parse(content: &str) -> Result<FileContent> {
+        parse_content(content.to_string(), &mut Includes::default())
+    }
+
+    #[test]
+    fn parses_header_and_cube() {
+        let content = parse("16; 8 8 8\n\n(0 1 2; 3 4 5)\n").unwrap();
+        assert_eq!(content.resolution, 16);
+        assert_eq!(content.origin, Vec3::splat(8.0));
+        let GeometryObject::Cube(cube) = content.obj else {
+            panic!("Expected cube, got {:?}", content.obj);
+        };
+        assert_eq!(cube.corner, IVec3::new(0, 1, 2));
+        assert_eq!(cube.size, IVec3::new(3, 4, 5));
+        assert_eq!(content.scale, Vec3::ONE);
+        assert_eq!(content.unit, 1.0);
+    }
+
+    #[test]
+    fn parses_scale_and_unit() {
+        let content = parse(
+            "16; 8 8 8; scale 1 2 0.5; unit 100; v2
+(0 0 0; 1 1 1) + (1 0 0; 1 1 1)",
+        )
+        .unwrap();
+        assert_eq!(content.scale, Vec3::new(1.0, 2.0, 0.5));
+        assert_eq!(content.unit, 100.0);
+        assert_eq!(content.obj.get_voxels().len(), 2);
+
+        let transform = Transform::new(&content, Axes::default());
+        assert_eq!(
+            transform.model_position(IVec3::new(24, 24, 24)),
+            Vec3::new(100.0, 200.0, 50.0)
+        );
+    }
+
+    #[test]
+    fn parses_nested_operators() {
+        let content = parse("1; 0 0 0\n-\n  +(0 0 0; 2 2 2)(2 0 0; 1 1 1)\n  &(0 0 0; 1 1 1) w h g/(0 0 0; 1 1 1)(0 0 0; 1 1 1)").unwrap();
+        let GeometryObject::Minus(left, right) = content.obj else {
+            panic!("Expected minus, got {:?}", content.obj);
+        };
+        assert!(matches!(*left, GeometryObject::Union(_, _)));
+        let GeometryObject::Intersection(_, right) = *right else {
+            panic!("Expected intersection, got {:?}", right);
+        };
+        let GeometryObject::Wireframe(inner, _) = *right else {
+            panic!("Expected wireframe, got {:?}", right);
+        };
+        let GeometryObject::Hull(inner, _) = *inner else {
+            panic!("Expected hull, got {:?}", inner);
+        };
+        let GeometryObject::Grow(inner, _) = *inner else {
+            panic!("Expected grow, got {:?}", inner);
+        };
+        assert!(matches!(*inner, GeometryObject::SymmetricDifference(_, _)));
+    }
+
+    #[test]
+    fn parses_neighbourhoods() {
+        let grow = |content: &str| match parse(content).unwrap().obj {
+            GeometryObject::Grow(_, neighbourhood) => neighbourhood,
+            obj => panic!("Expected grow, got {:?}", obj),
+        };
+        assert_eq!(grow("1; 0 0 0\ng(0 0 0; 1 1 1)"), Neighbourhood::default());
+        assert_eq!(grow("1; 0 0 0\ng3(0 0 0; 1 1 1)").radius, 3);
+        let neighbourhood = grow("1; 0 0 0\ng(r=2, n=6, axes=xz) (0 0 0; 1 1 1)");
+        assert_eq!(neighbourhood.radius, 2);
+        assert_eq!(neighbourhood.kind, NeighbourhoodKind::Faces);
+        assert_eq!(neighbourhood.axes, IVec3::new(1, 0, 1));
+        let neighbourhood = grow("1; 0 0 0\ng2 (n=sphere)(0 0 0; 1 1 1)");
+        assert_eq!(neighbourhood.radius, 2);
+        assert_eq!(neighbourhood.kind, NeighbourhoodKind::Sphere);
+
+        let invalid = [
+            "1; 0 0 0\ng0(0 0 0; 1 1 1)",
+            "1; 0 0 0\ng33(0 0 0; 1 1 1)",
+            "1; 0 0 0\ng(r=2147483647)(0 0 0; 1 1 1)",
+            "1; 0 0 0\ng(r=1, n=7)(0 0 0; 1 1 1)",
+            "1; 0 0 0\ng(axes=w)(0 0 0; 1 1 1)",
+            "1; 0 0 0\ng(q=1)(0 0 0; 1 1 1)",
+            "1; 0 0 0\ng(r=2)",
+        ];
+        for content in invalid {
+            assert!(parse(content).is_err(), "Expected error for {:?}", content);
+        }
+    }
+
+    #[test]
+    fn parses_morphological_operators() {
+        let content = parse("1; 0 0 0\ns o2 c(n=6)(0 0 0; 1 1 1)").unwrap();
+        let GeometryObject::Shrink(inner, _) = content.obj else {
+            panic!("Expected shrink, got {:?}", content.obj);
+        };
+        let GeometryObject::Open(inner, neighbourhood) = *inner else {
+            panic!("Expected open, got {:?}", inner);
+        };
+        assert_eq!(neighbourhood.radius, 2);
+        let GeometryObject::Close(_, neighbourhood) = *inner else {
+            panic!("Expected close, got {:?}", inner);
+        };
+        assert_eq!(neighbourhood.kind, NeighbourhoodKind::Faces);
+    }
+
+    #[test]
+    fn parses_shells() {
+        let shell = |content: &str| match parse(content).unwrap().obj {
+            GeometryObject::Shell(_, neighbourhood, open) => (neighbourhood.radius, open),
+            obj => panic!("Expected shell, got {:?}", obj),
+        };
+        assert_eq!(shell("1; 0 0 0\ne(0 0 0; 1 1 1)"), (1, None));
+        assert_eq!(shell("1; 0 0 0\ne2(0 0 0; 1 1 1)"), (2, None));
+        assert_eq!(
+            shell("1; 0 0 0\ne(r=3, open=+y)(0 0 0; 1 1 1)"),
+            (3, Some(IVec3::Y))
+        );
+        assert_eq!(
+            shell("1; 0 0 0\ne2(open=-z)(0 0 0; 1 1 1)"),
+            (2, Some(-IVec3::Z))
+        );
+        assert!(parse("1; 0 0 0\ne(open=w)(0 0 0; 1 1 1)").is_err());
+        assert!(parse("1; 0 0 0\ng(open=y)(0 0 0; 1 1 1)").is_err());
+    }
+
+    #[test]
+    fn parses_repeats() {
+        let content = parse("1; 0 0 0\nr (4 0 0; 3; 0 0 2; 2) (0 0 0; 1 1 1)").unwrap();
+        let GeometryObject::Repeat(_, steps) = content.obj else {
+            panic!("Expected repeat, got {:?}", content.obj);
+        };
+        assert_eq!(steps, [(IVec3::new(4, 0, 0), 3), (IVec3::new(0, 0, 2), 2)]);
+
+        let invalid = [
+            "1; 0 0 0\nr(0 0 0; 1 1 1)",
+            "1; 0 0 0\nr(4 0 0; 3; 1 0 0)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nr(4 0 0; 0)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nr(4 0 0; 2)",
+            "1; 0 0 0\nr(1 0 0; 2147483647)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nr(2000000000 0 0; 3)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nr(1500000000 0 0; 1; -1500000000 0 0; 1)(0 0 0; 1 1 1)",
+        ];
+        for content in invalid {
+            assert!(parse(content).is_err(), "Expected error for {:?}", content);
+        }
+    }
+
+    #[test]
+    fn parses_crops_and_cuts() {
+        let crop = |content: &str| match parse(content).unwrap().obj {
+            GeometryObject::Crop(_, bounds) => bounds,
+            obj => panic!("Expected crop, got {:?}", obj),
+        };
+        let bounds = crop("1; 0 0 0\nb(1 2 3; 4 5 6)(0 0 0; 1 1 1)");
+        assert_eq!(bounds.min, IVec3::new(1, 2, 3));
+        assert_eq!(bounds.max, IVec3::new(5, 7, 9));
+        assert_eq!(crop("1; 0 0 0\nt(x < 8)(0 0 0; 1 1 1)").max.x, 8);
+        assert_eq!(crop("1; 0 0 0\nt(y <= 8)(0 0 0; 1 1 1)").max.y, 9);
+        assert_eq!(crop("1; 0 0 0\nt(z > -2)(0 0 0; 1 1 1)").min.z, -1);
+        let bounds = crop("1; 0 0 0\nt (x >= 3) (0 0 0; 1 1 1)");
+        assert_eq!(bounds.min, IVec3::new(3, i32::MIN, i32::MIN));
+        assert_eq!(bounds.max, IVec3::MAX);
+
+        let invalid = [
+            "1; 0 0 0\nt(w < 1)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nt(x = 1)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nt(x <)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nt(x <= 2147483647)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nt(y > 2147483647)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nb(0 0 0; 0 1 1)(0 0 0; 1 1 1)",
+            "1; 0 0 0\nb(2147483647 0 0; 1 1 1)(0 0 0; 1 1 1)",
+        ];
+        for content in invalid {
+            assert!(parse(content).is_err(), "Expected error for {:?}", content);
+        }
+    }
+
+    #[test]
+    fn parses_infix_expressions() {
+        let voxels = |content: &str| parse(content).unwrap().obj.get_voxels();
+        let a = "(0 0 0; 4 4 4)";
+        let b = "(2 0 0; 4 4 4)";
+        let c = "(0 0 0; 3 1 4)";
+        let cases = [
+            (format!("{a} + {b} - {c}"), format!("- + {a} {b} {c}")),
+            (format!("{a} - {b} & {c}"), format!("- {a} & {b} {c}")),
+            (format!("{a} - ({b} + {c})"), format!("- {a} + {b} {c}")),
+            (format!("{a} / {b} + {c}"), format!("+ / {a} {b} {c}")),
+            (format!("g2 {a} & {c}"), format!("& g2 {a} {c}")),
+            (format!("s ({a} + {b})"), format!("s + {a} {b}")),
+            (
+                format!("b(0 0 0; 2 2 2) ({b})"),
+                format!("b(0 0 0; 2 2 2) {b}"),
+            ),
+        ];
+        for (infix, prefix) in cases {
+            assert_eq!(
+                voxels(&format!("16; 8 8 8; v2\n{}", infix)),
+                voxels(&format!("16; 8 8 8\n{}", prefix)),
+                "{}",
+                infix
+            );
+        }
+    }
+
+    #[test]
+    fn infix_syntax_allows_comments() {
+        let content = parse(
+            "16; 8 8 8; v2\n# The base\n(0 0 0; 2 1 1) // left\n\n  + (4 0 0; 1 1 1) # right\n",
+        )
+        .unwrap();
+        assert_eq!(content.obj.get_voxels().len(), 3);
+    }
+
+    #[test]
+    fn rejects_invalid_infix_input() {
+        let invalid = [
+            "16; 8 8 8; v3\n(0 0 0; 1 1 1)",
+            "16; 8 8 8; v2\n+ (0 0 0; 1 1 1) (0 0 0; 1 1 1)",
+            "16; 8 8 8; v2\n(0 0 0; 1 1 1) +",
+            "16; 8 8 8; v2\n((0 0 0; 1 1 1) + (0 0 0; 1 1 1)",
+            "16; 8 8 8; v2\n(0 0 0; 1 1 1))",
+            "16; 8 8 8; v2\n(0 0 0; 1 1 1) (0 0 0; 1 1 1)",
+        ];
+        for content in invalid {
+            assert!(parse(content).is_err(), "Expected error for {:?}", content);
+        }
+    }
+
+    #[test]
+    fn ignores_comments_and_layout() {
+        let content = parse(
+            "# A step\r\n\r\n16; 8 8 8; // centered\r\n+ # the base\r\n  (\r\n    0 0 0;\r\n    2 1 1;\r\n  ) // left\r\n\t( 0 1 0 ; 1 1 1 ; )\r\n",
+        )
+        .unwrap();
+        assert_eq!(content.resolution, 16);
+        assert_eq!(content.origin, Vec3::splat(8.0));
+        assert_eq!(content.obj.get_voxels().len(), 3);
+
+        // The quoted file name is read whole, so only the missing operand
+        // of `+` is reported.
+        let err = parse("16; 8 8 8\n+ include \"#a//b.txt\" # comment").unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("Unexpected end of file"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn prefix_syntax_keeps_nested_symmetric_differences() {
+        let voxels = |content: &str| parse(content).unwrap().obj.get_voxels();
+        let nested = "16; 8 8 8\n//(0 0 0; 1 1 1)(1 0 0; 1 1 1)(0 0 0; 3 1 1)";
+        assert_eq!(voxels(nested), HashSet::from([IVec3::new(2, 0, 0)]));
+        assert_eq!(
+            voxels("16; 8 8 8\n// two voxels\n+(0 0 0; 1 1 1) //\n(1 0 0; 1 1 1)").len(),
+            2
+        );
+        assert!(parse("16; 8 8 8; v2\n(0 0 0; 1 1 1) //(1 0 0; 1 1 1)").is_ok());
+    }
+
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("voxel-mesher-{}", name));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        for (file, content) in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn includes_relative_files() {
+        let dir = write_files(
+            "include",
+            &[
+                (
+                    "main.txt",
+                    "16; 8 8 8\n+ include \"parts/a.txt\" (4 0 0; 1 1 1)",
+                ),
+                ("parts/a.txt", "16; 0 0 0\ninclude \"b.txt\""),
+                ("parts/b.txt", "16; 0 0 0\n(0 0 0; 2 1 1)"),
+            ],
+        );
+        let content = load_content(&dir.join("main.txt")).unwrap();
+        assert_eq!(content.origin, Vec3::splat(8.0));
+        assert_eq!(content.obj.get_voxels().len(), 3);
+    }
+
+    #[test]
+    fn loads_copies_with_includes_of_original() {
+        let dir = write_files(
+            "include-copy",
+            &[
+                ("models/main.txt", "16; 8 8 8\n(9 0 0; 1 1 1)"),
+                ("models/part.txt", "16; 0 0 0\n(0 0 0; 2 1 1)"),
+                (
+                    "copy.txt",
+                    "16; 8 8 8\n+ include \"part.txt\" (4 0 0; 1 1 1)",
+                ),
+            ],
+        );
+        let content = load_content_as(&dir.join("copy.txt"), &dir.join("models/main.txt"));
+        assert_eq!(content.unwrap().obj.get_voxels().len(), 3);
+        assert!(load_content(&dir.join("copy.txt")).is_err());
+    }
+
+    #[test]
+    fn rejects_include_cycles() {
+        let dir = write_files(
+            "include-cycle",
+            &[
+                ("a.txt", "16; 0 0 0\n+(0 0 0; 1 1 1) include \"b.txt\""),
+                ("b.txt", "16; 0 0 0\ninclude \"a.txt\""),
+            ],
+        );
+        let err = load_content(&dir.join("a.txt")).unwrap_err();
+        assert!(format!("{:#}", err).contains("Include cycle"), "{:#}", err);
+    }
+
+    #[test]
+    fn rescales_included_resolution() {
+        let dir = write_files(
+            "include-scale",
+            &[
+                ("fine.txt", "16; 0 0 0\ninclude \"coarse.txt\""),
+                ("coarse.txt", "8; 0 0 0\n(1 0 0; 1 1 1)"),
+                ("odd.txt", "3; 0 0 0\n(0 0 0; 1 1 1)"),
+                ("mismatch.txt", "16; 0 0 0\ninclude \"odd.txt\""),
+            ],
+        );
+        let voxels = load_content(&dir.join("fine.txt"))
+            .unwrap()
+            .obj
+            .get_voxels();
+        let expected = GeometryObject::Cube(Cube {
+            corner: IVec3::new(2, 0, 0),
+            size: IVec3::splat(2),
+        });
+        assert_eq!(voxels, expected.get_voxels());
+        assert!(load_content(&dir.join("mismatch.txt")).is_err());
+        assert!(load_content(&dir.join("missing.txt")).is_err());
+    }
+
+    #[test]
+    fn loads_structured_models() {
+        let dir = write_files(
+            "structured",
+            &[
+                (
+                    "model.txt",
+                    "16; 8 8 8; scale 1 2 1; unit 100\n- ++ (0 0 0; 4 4 4) include \"part.txt\" t(y < 2) (8 0 0; 2 4 1) g(r=2, n=6) (1 1 1; 1 1 1)",
+                ),
+                ("part.txt", "16; 0 0 0\ne(open=+y) (4 0 0; 3 3 3)"),
+                (
+                    "model.json",
+                    r#"{
+                        "resolution": 16,
+                        "origin": [8, 8, 8],
+                        "scale": [1, 2, 1],
+                        "unit": 100,
+                        "geometry": {"minus": [
+                            {"union": [
+                                {"cube": {"corner": [0, 0, 0], "size": [4, 4, 4]}},
+                                {"include": "part.txt"},
+                                {"crop": {
+                                    "geometry": {"cube": {"corner": [8, 0, 0], "size": [2, 4, 1]}},
+                                    "max": [null, 2, null]
+                                }}
+                            ]},
+                            {"grow": {
+                                "geometry": {"cube": {"corner": [1, 1, 1], "size": [1, 1, 1]}},
+                                "neighbourhood": {"radius": 2, "kind": "faces"}
+                            }}
+                        ]}
+                    }"#,
+                ),
+                (
+                    "model.ron",
+                    r#"(
+                        resolution: 16,
+                        origin: (8, 8, 8),
+                        scale: (1, 2, 1),
+                        unit: 100,
+                        geometry: minus(
+                            union([
+                                cube(corner: (0, 0, 0), size: (4, 4, 4)),
+                                include("part.txt"),
+                                crop(
+                                    geometry: cube(corner: (8, 0, 0), size: (2, 4, 1)),
+                                    max: (None, Some(2), None),
+                                ),
+                            ]),
+                            grow(
+                                geometry: cube(corner: (1, 1, 1), size: (1, 1, 1)),
+                                neighbourhood: (radius: 2, kind: faces),
+                            ),
+                        ),
+                    )"#,
+                ),
+            ],
+        );
+        let expected = load_content(&dir.join("model.txt")).unwrap();
+        for file in ["model.json", "model.ron"] {
+            let content = load_content(&dir.join(file)).unwrap();
+            assert_eq!(content.resolution, expected.resolution);
+            assert_eq!(content.origin, expected.origin);
+            assert_eq!(content.scale, expected.scale);
+            assert_eq!(content.unit, expected.unit);
+            assert_eq!(
+                content.obj.get_voxels(),
+                expected.obj.get_voxels(),
+                "{}",
+                file
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_structured_models() {
+        let dir = write_files(
+            "structured-invalid",
+            &[
+                (
+                    "unknown.json",
+                    r#"{"resolution": 16, "origin": [0, 0, 0], "geometry": {"sphere": 3}}"#,
+                ),
+                (
+                    "size.json",
+                    r#"{"resolution": 16, "origin": [0, 0, 0], "geometry": {"cube": {"corner": [0, 0, 0], "size": [1, 0, 1]}}}"#,
+                ),
+                (
+                    "empty.json",
+                    r#"{"resolution": 16, "origin": [0, 0, 0], "geometry": {"union": []}}"#,
+                ),
+                (
+                    "radius.ron",
+                    "(resolution: 16, origin: (0, 0, 0), geometry: grow(geometry: cube(corner: (0, 0, 0), size: (1, 1, 1)), neighbourhood: (radius: 0)))",
+                ),
+                (
+                    "large-radius.ron",
+                    "(resolution: 16, origin: (0, 0, 0), geometry: grow(geometry: cube(corner: (0, 0, 0), size: (1, 1, 1)), neighbourhood: (radius: 1000)))",
+                ),
+                (
+                    "header.ron",
+                    "(resolution: 16, geometry: cube(corner: (0, 0, 0), size: (1, 1, 1)))",
+                ),
+                (
+                    "unit.ron",
+                    "(resolution: 16, origin: (0, 0, 0), unit: inf, geometry: cube(corner: (0, 0, 0), size: (1, 1, 1)))",
+                ),
+                (
+                    "scale.json",
+                    r#"{"resolution": 16, "origin": [0, 0, 0], "scale": [1, 0, 1], "geometry": {"cube": {"corner": [0, 0, 0], "size": [1, 1, 1]}}}"#,
+                ),
+            ],
+        );
+        for file in [
+            "unknown.json",
+            "size.json",
+            "empty.json",
+            "radius.ron",
+            "large-radius.ron",
+            "header.ron",
+            "unit.ron",
+            "scale.json",
+        ] {
+            assert!(
+                load_content(&dir.join(file)).is_err(),
+                "Expected error for {}",
+                file
+            );
+        }
+    }
+
+    #[test]
+    fn parses_all_models() {
+        for entry in std::fs::read_dir("models").unwrap() {
+            let path = entry.unwrap().path();
+            if let Err(err) = load_content(&path) {
+                panic!("Failed to parse {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    #[test]
+    fn formatting_preserves_geometry() {
+        let contents = [
+            "16; 8 8 8\n- h --+ (0 0 0; 6 6 16) (0 0 5; 11 6 6) (0 0 6; 1 6 4) (1 0 7; 1 6 2) + (1 1 0; 4 4 1) (1 1 15; 4 4 1)",
+            "16; 8 8 8\n// a\n/ / (0 0 0; 3 3 3) # b\n  e(r=2, open=+z) (0 0 0; 8 8 8) t(y >= 2) g(n=s, r=2) (1 1 1; 2 2 2)",
+            "16; 8 8 8; v2\n(0 0 0; 4 4 4) - ((1 0 0; 2 2 2) - (1 0 0; 1 1 1)) # c\n & o(axes=xy) r(1 0 0; 3) (0 0 0; 1 1 1)",
+            "16; 8 8 8; v2\n((0 0 0; 4 4 4) & (1 1 1; 4 4 4)) + c ((0 0 0; 1 1 1) / (0 0 0; 2 1 1))",
+        ];
+        for content in contents {
+            let formatted = format_document(&parse_document(content).unwrap());
+            assert_eq!(
+                parse(&formatted).unwrap().obj.get_voxels(),
+                parse(content).unwrap().obj.get_voxels(),
+                "{}",
+                formatted
+            );
+            let reformatted = format_document(&parse_document(&formatted).unwrap());
+            assert_eq!(reformatted, formatted);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let invalid = [
+            "",
+            "16\n(0 0 0; 1 1 1)",
+            "16; 8 8\n(0 0 0; 1 1 1)",
+            "0; 8 8 8\n(0 0 0; 1 1 1)",
+            "x; 8 8 8\n(0 0 0; 1 1 1)",
+            "16; 8 8 8",
+            "16; 8 8 8\n(0 0 0; 1 0 1)",
+            "16; 8 8 8\n(0 0 0; 1 1)",
+            "16; 8 8 8\n(0 0 0 1 1 1)",
+            "16; 8 8 8\n(0 0 0; 1 1 1",
+            "16; 8 8 8\n+(0 0 0; 1 1 1)",
+            "16; 8 8 8\n?(0 0 0; 1 1 1)",
+            "16; 8 8 8\n(0 0 0; 1 1 1)(0 0 0; 1 1 1)",
+            "16; 8 8 8; v3\n(0 0 0; 1 1 1)",
+            "16; 8 8 8; scale 1 2\n(0 0 0; 1 1 1)",
+            "16; 8 8 8; scale 1 0 1\n(0 0 0; 1 1 1)",
+            "16; 8 8 8; unit -1\n(0 0 0; 1 1 1)",
+            "16; 8 8 8; unit inf\n(0 0 0; 1 1 1)",
+            "16; 8 8 8; unit\n(0 0 0; 1 1 1)",
+            "16; 8 8 8; scale\n(0 0 0; 1 1 1)",
+            "16; 8 8 8; scale 1 NaN 1\n(0 0 0; 1 1 1)",
+            "16; 8 8 8; size 2\n(0 0 0; 1 1 1)",
+        ];
+        for content in invalid {
+            assert!(parse(content).is_err(), "Expected error for {:?}", content);
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, ensure};
+use bevy_math::{I64Vec3, IVec3, Vec2};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    app::Cube,
+    geometry::{Geometry, Quad, generate_quads},
+    obj::Transform,
+    triangulate::Triangle,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColliderFormat {
+    /// A list of boxes with their min and max corners in `<name>_boxes.json`.
+    Json,
+    /// The boxes as a mesh in `<name>_collision.obj`.
+    Obj,
+}
+
+/// Splits the solid voxels into disjoint boxes, merging greedily along z,
+/// then y, then x, like the faces of `generate_quads` but in three
+/// dimensions.
+pub fn decompose_boxes(geometry: &Geometry) -> Vec<Cube> {
+    let size = geometry.size.as_uvec3();
+    let (sx, sy, sz) = (size.x as usize, size.y as usize, size.z as usize);
+    let mut free = geometry.voxels.clone();
+    let mut boxes = Vec::new();
+    for x in 0..sx {
+        for y in 0..sy {
+            for z in 0..sz {
+                if !free[x][y][z] {
+                    continue;
+                }
+                let mut dz = 1;
+                while z + dz < sz && free[x][y][z + dz] {
+                    dz += 1;
+                }
+                let mut dy = 1;
+                while y + dy < sy && (z..z + dz).all(|k| free[x][y + dy][k]) {
+                    dy += 1;
+                }
+                let mut dx = 1;
+                while x + dx < sx && (y..y + dy).all(|j| (z..z + dz).all(|k| free[x + dx][j][k])) {
+                    dx += 1;
+                }
+                for column in &mut free[x..x + dx] {
+                    for row in &mut column[y..y + dy] {
+                        row[z..z + dz].fill(false);
+                    }
+                }
+                boxes.push(Cube {
+                    corner: geometry.min + IVec3::new(x as i32, y as i32, z as i32),
+                    size: IVec3::new(dx as i32, dy as i32, dz as i32),
+                });
+            }
+        }
+    }
+    boxes
+}
+
+#[derive(Debug, Serialize)]
+struct BoxCollider {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+/// Lists the boxes with their corners in exported coordinates.
+pub fn format_boxes_json(boxes: &[Cube], transform: &Transform) -> String {
+    let colliders: Vec<_> = boxes
+        .iter()
+        .map(|cube| {
+            let a = transform.apply(cube.corner);
+            let b = transform.apply(cube.corner + cube.size);
+            BoxCollider {
+                min: a.min(b).to_array(),
+                max: a.max(b).to_array(),
+            }
+        })
+        .collect();
+    serde_json::to_string_pretty(&colliders).unwrap() + "\n"
+}
+
+/// The six faces of every box.
+pub fn box_quads(boxes: &[Cube]) -> Vec<Quad> {
+    boxes
+        .iter()
+        .flat_map(|cube| {
+            let (x, y, z) = (cube.size.x, cube.size.y, cube.size.z);
+            generate_quads(&Geometry {
+                min: cube.corner,
+                size: cube.size,
+                voxels: vec![vec![vec![true; z as usize]; y as usize]; x as usize],
+            })
+        })
+        .collect()
+}
+
+/// The largest coordinate the convex hull accepts. Differences of points are
+/// then below 2^20, so the triple products of the orientation tests fit in an
+/// `i64`.
+const MAX_HULL_COORDINATE: i64 = 1 << 19;
+
+/// The convex hull of the vertices of the quads, as triangles wound counter
+/// clockwise seen from outside. Starting from a tetrahedron, the point
+/// farthest outside the hull is added until no point is left outside or the
+/// hull has `max_vertices` vertices, so that a capped hull lies inside the
+/// full one.
+pub fn convex_hull(quads: &[Quad], max_vertices: usize) -> Result<Vec<Triangle>> {
+    let mut points: Vec<I64Vec3> = quads
+        .iter()
+        .flat_map(|q| q.vertices)
+        .map(|v| v.as_i64vec3())
+        .collect();
+    points.sort_by_key(|p| p.to_array());
+    points.dedup();
+    ensure!(points.len() >= 4, "Not enough vertices for a convex hull");
+    ensure!(
+        points
+            .iter()
+            .all(|p| p.abs().max_element() <= MAX_HULL_COORDINATE),
+        "The model is too large for a convex hull, coordinates must be within {}",
+        MAX_HULL_COORDINATE
+    );
+
+    // Exact arithmetic keeps the orientation tests robust on the lattice. The
+    // squared length of a cross product needs more than 64 bits.
+    let argmax =
+        |key: &dyn Fn(I64Vec3) -> i128| (0..points.len()).max_by_key(|&i| key(points[i])).unwrap();
+    let length_squared = |v: I64Vec3| v.to_array().map(|x| x as i128 * x as i128).iter().sum();
+    let a = 0;
+    let b = argmax(&|p| length_squared(p - points[a]));
+    let c = argmax(&|p| length_squared((points[b] - points[a]).cross(p - points[a])));
+    let normal = (points[b] - points[a]).cross(points[c] - points[a]);
+    let d = argmax(&|p| normal.dot(p - points[a]).abs() as i128);
+    ensure!(
+        normal.dot(points[d] - points[a]) != 0,
+        "The model is flat and has no convex hull"
+    );
+
+    let plane = |face: &[usize; 3]| {
+        let [a, b, c] = face.map(|i| points[i]);
+        ((b - a).cross(c - a), a)
+    };
+    let mut faces: Vec<[usize; 3]> = [[a, b, c, d], [a, b, d, c], [a, c, d, b], [b, c, d, a]]
+        .into_iter()
+        .map(|[a, b, c, other]| {
+            let (normal, origin) = plane(&[a, b, c]);
+            if normal.dot(points[other] - origin) > 0 {
+                [a, c, b]
+            } else {
+                [a, b, c]
+            }
+        })
+        .collect();
+
+    let mut vertex_count = 4;
+    while vertex_count < max_vertices {
+        let planes: Vec<_> = faces.iter().map(plane).collect();
+        let mut farthest = None;
+        let mut max_distance = 0.0;
+        for (i, &p) in points.iter().enumerate() {
+            for (normal, origin) in &planes {
+                let height = normal.dot(p - *origin);
+                if height <= 0 {
+                    continue;
+                }
+                let distance = height as f64 / normal.as_dvec3().length();
+                if distance > max_distance {
+                    max_distance = distance;
+                    farthest = Some(i);
+                }
+            }
+        }
+        let Some(i) = farthest else {
+            break;
+        };
+
+        // Replace the faces the point sees with a fan from the point to the
+        // edges around them.
+        let (visible, kept): (Vec<_>, Vec<_>) = faces
+            .into_iter()
+            .zip(&planes)
+            .partition(|(_, (normal, origin))| normal.dot(points[i] - *origin) > 0);
+        let edges: HashSet<(usize, usize)> = visible
+            .iter()
+            .flat_map(|([a, b, c], _)| [(*a, *b), (*b, *c), (*c, *a)])
+            .collect();
+        faces = kept.into_iter().map(|(face, _)| face).collect();
+        for &(a, b) in &edges {
+            if !edges.contains(&(b, a)) {
+                faces.push([a, b, i]);
+            }
+        }
+        vertex_count = faces.iter().flatten().collect::<HashSet<_>>().len();
+    }
+
+    Ok(faces
+        .iter()
+        .map(|face| Triangle {
+            vertices: face.map(|i| points[i].as_ivec3()),
+            normal: plane(face).0.as_vec3().normalize(),
+            uvs: [Vec2::ZERO; 3],
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_math::Vec3;
+
+    use super::*;
+    use crate::{
+        app::FileContent,
+        geometry::{GeometryObject, content_of, convert_to_geometry, cube, quads_of},
+        obj::Axes,
+    };
+
+    fn box_voxels(boxes: &[Cube]) -> Vec<IVec3> {
+        boxes
+            .iter()
+            .flat_map(|b| cube(b.corner, b.size).get_voxels())
+            .collect()
+    }
+
+    #[test]
+    fn decomposes_into_disjoint_boxes() {
+        // An L-shaped block and a separate pillar.
+        let obj = GeometryObject::Union(
+            Box::new(GeometryObject::Union(
+                Box::new(cube(IVec3::ZERO, IVec3::new(4, 2, 3))),
+                Box::new(cube(IVec3::ZERO, IVec3::new(1, 5, 3))),
+            )),
+            Box::new(cube(IVec3::new(6, -2, 0), IVec3::new(1, 3, 1))),
+        );
+        let expected = obj.get_voxels();
+        let boxes = decompose_boxes(&convert_to_geometry(&content_of(obj)).unwrap());
+        assert_eq!(boxes.len(), 3);
+
+        let voxels = box_voxels(&boxes);
+        let unique: HashSet<_> = voxels.iter().copied().collect();
+        assert_eq!(unique.len(), voxels.len());
+        assert_eq!(unique, expected);
+        assert_eq!(box_quads(&boxes).len(), 18);
+    }
+
+    fn hull(obj: GeometryObject, max_vertices: usize) -> (Vec<Triangle>, Vec<IVec3>) {
+        let quads = quads_of(obj);
+        let points = quads.iter().flat_map(|q| q.vertices).collect();
+        (convex_hull(&quads, max_vertices).unwrap(), points)
+    }
+
+    fn hull_vertices(triangles: &[Triangle]) -> HashSet<IVec3> {
+        triangles.iter().flat_map(|t| t.vertices).collect()
+    }
+
+    /// Whether the point lies inside or on the plane of every triangle.
+    fn inside(triangles: &[Triangle], p: IVec3) -> bool {
+        triangles.iter().all(|t| {
+            let [a, b, c] = t.vertices.map(|v| v.as_i64vec3());
+            (b - a).cross(c - a).dot(p.as_i64vec3() - a) <= 0
+        })
+    }
+
+    #[test]
+    fn hull_of_box_is_its_corners() {
+        let (triangles, _) = hull(cube(IVec3::ZERO, IVec3::new(2, 3, 4)), 64);
+        let corners: HashSet<_> = (0..8)
+            .map(|i| IVec3::new(i & 1, (i >> 1) & 1, i >> 2) * IVec3::new(2, 3, 4))
+            .collect();
+        assert_eq!(hull_vertices(&triangles), corners);
+        let center = Vec3::new(1.0, 1.5, 2.0);
+        for triangle in &triangles {
+            let centroid = triangle.vertices.iter().map(|v| v.as_vec3()).sum::<Vec3>() / 3.0;
+            assert!(triangle.normal.dot(centroid - center) > 0.0);
+        }
+    }
+
+    #[test]
+    fn hull_encloses_all_vertices_within_cap() {
+        let obj = GeometryObject::Union(
+            Box::new(cube(IVec3::ZERO, IVec3::new(4, 1, 4))),
+            Box::new(cube(IVec3::new(1, 1, 1), IVec3::new(1, 3, 2))),
+        );
+        let (triangles, points) = hull(obj.clone(), 64);
+        assert!(points.iter().all(|&p| inside(&triangles, p)));
+        assert_eq!(hull_vertices(&triangles).len(), 12);
+
+        let (capped, _) = hull(obj, 6);
+        assert_eq!(hull_vertices(&capped).len(), 6);
+        assert!(
+            hull_vertices(&capped)
+                .iter()
+                .all(|&p| inside(&triangles, p))
+        );
+    }
+
+    #[test]
+    fn hull_rejects_large_coordinates() {
+        let far = IVec3::splat(MAX_HULL_COORDINATE as i32);
+        let (triangles, _) = hull(cube(far - IVec3::ONE, IVec3::ONE), 64);
+        assert_eq!(triangles.len(), 12);
+        assert!(convex_hull(&quads_of(cube(far, IVec3::ONE)), 64).is_err());
+    }
+
+    #[test]
+    fn formats_boxes_in_exported_coordinates() {
+        let obj = cube(IVec3::new(2, 0, 0), IVec3::new(2, 4, 1));
+        let content = FileContent {
+            resolution: 2,
+            ..content_of(obj)
+        };
+        let boxes = decompose_boxes(&convert_to_geometry(&content).unwrap());
+        let transform = Transform::new(&content, Axes::default());
+        let json: serde_json::Value =
+            serde_json::from_str(&format_boxes_json(&boxes, &transform)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{"min": [-2.0, 0.0, 0.0], "max": [-1.0, 2.0, 0.5]}])
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use bevy_math::IVec3;
+use image::Rgba;
+
+use crate::{
+    app::{FileContent, load_content_as},
+    geometry::{Quad, generate_quads, geometry_from_voxels},
+    texture::sort_quads,
+};
+
+pub const UNCHANGED: Rgba<u8> = Rgba([160, 160, 160, 255]);
+pub const ADDED: Rgba<u8> = Rgba([60, 200, 60, 255]);
+pub const REMOVED: Rgba<u8> = Rgba([220, 50, 50, 255]);
+
+/// The voxels of two versions of a model, split by whether they were added,
+/// removed or kept.
+pub struct VoxelDiff {
+    pub added: HashSet<IVec3>,
+    pub removed: HashSet<IVec3>,
+    pub unchanged: HashSet<IVec3>,
+}
+
+impl VoxelDiff {
+    pub fn new(before: &HashSet<IVec3>, after: &HashSet<IVec3>) -> Self {
+        Self {
+            added: after.difference(before).copied().collect(),
+            removed: before.difference(after).copied().collect(),
+            unchanged: before.intersection(after).copied().collect(),
+        }
+    }
+
+    /// Meshes every part on its own, returning the quads together with the
+    /// color of the part they belong to.
+    pub fn colored_quads(&self) -> Result<(Vec<Quad>, Vec<Rgba<u8>>)> {
+        let mut quads = Vec::new();
+        let mut colors = Vec::new();
+        for (voxels, color) in [
+            (&self.unchanged, UNCHANGED),
+            (&self.added, ADDED),
+            (&self.removed, REMOVED),
+        ] {
+            if voxels.is_empty() {
+                continue;
+            }
+            let mut part = generate_quads(&geometry_from_voxels(voxels)?);
+            sort_quads(&mut part);
+            colors.extend(std::iter::repeat_n(color, part.len()));
+            quads.extend(part);
+        }
+        ensure!(!quads.is_empty(), "No voxels found");
+        Ok((quads, colors))
+    }
+}
+
+impl Display for VoxelDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} voxels added, {} removed, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.unchanged.len()
+        )
+    }
+}
+
+/// A model file as of a git revision, written to the temporary directory so
+/// that it is not picked up along with the model files. The copy is removed
+/// when dropped.
+pub struct RevisionCopy {
+    file: PathBuf,
+    path: PathBuf,
+}
+
+impl RevisionCopy {
+    pub fn new(file: &Path, rev: &str) -> Result<Self> {
+        let name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(anyhow!("Invalid file name: {}", file.display()))?;
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let output = Command::new("git")
+            .arg("show")
+            .arg(format!("{}:./{}", rev, name))
+            .current_dir(dir)
+            .output()
+            .context("Failed to run git")?;
+        ensure!(
+            output.status.success(),
+            "Failed to read {} at {}: {}",
+            file.display(),
+            rev,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        let rev_name: String = rev
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = std::env::temp_dir().join(format!(
+            "voxel-mesher-{}-{}.{}",
+            std::process::id(),
+            rev_name,
+            name
+        ));
+        std::fs::write(&path, output.stdout)?;
+        Ok(Self {
+            file: file.to_path_buf(),
+            path,
+        })
+    }
+
+    /// Loads the copy, resolving its includes next to the model file.
+    pub fn load(&self) -> Result<FileContent> {
+        load_content_as(&self.path, &self.file)
+    }
+}
+
+impl Drop for RevisionCopy {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::cube;
+
+    #[test]
+    fn splits_voxels_by_change() {
+        let before = cube(IVec3::ZERO, IVec3::new(3, 1, 1)).get_voxels();
+        let after = cube(IVec3::X, IVec3::new(3, 1, 1)).get_voxels();
+        let diff = VoxelDiff::new(&before, &after);
+        assert_eq!(diff.added, HashSet::from([IVec3::new(3, 0, 0)]));
+        assert_eq!(diff.removed, HashSet::from([IVec3::ZERO]));
+        assert_eq!(diff.unchanged.len(), 2);
+        assert_eq!(diff.to_string(), "1 voxels added, 1 removed, 2 unchanged");
+
+        let (quads, colors) = diff.colored_quads().unwrap();
+        assert_eq!(quads.len(), colors.len());
+        let count = |color| colors.iter().filter(|&&c| c == color).count();
+        assert_eq!((count(UNCHANGED), count(ADDED), count(REMOVED)), (6, 6, 6));
+        let removed = quads.iter().zip(&colors).filter(|(_, c)| **c == REMOVED);
+        assert!(removed.flat_map(|(q, _)| q.vertices).all(|v| v.x <= 1));
+    }
+
+    #[test]
+    fn fails_without_voxels() {
+        let diff = VoxelDiff::new(&HashSet::new(), &HashSet::new());
+        assert!(diff.colored_quads().is_err());
+    }
+}
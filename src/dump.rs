@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+
+use bevy_math::IVec3;
+
+use crate::geometry::{Bounds, GeometryObject, Neighbourhood, NeighbourhoodKind};
+
+/// A node of the geometry tree together with its own evaluated voxels.
+pub struct DumpNode<'a> {
+    pub depth: usize,
+    pub obj: &'a GeometryObject,
+    pub voxels: HashSet<IVec3>,
+}
+
+/// Evaluates every node of the tree, in the order they appear in the model
+/// file. Nodes are evaluated bottom up from the voxels of their operands, so
+/// that every node is only evaluated once.
+pub fn evaluate_nodes(obj: &GeometryObject) -> Vec<DumpNode<'_>> {
+    let mut nodes = Vec::new();
+    evaluate_node(obj, 0, &mut nodes);
+    nodes
+}
+
+/// Appends the node and its operands to `nodes`, returning its index.
+fn evaluate_node<'a>(
+    obj: &'a GeometryObject,
+    depth: usize,
+    nodes: &mut Vec<DumpNode<'a>>,
+) -> usize {
+    let index = nodes.len();
+    nodes.push(DumpNode {
+        depth,
+        obj,
+        voxels: HashSet::new(),
+    });
+    let operands = obj
+        .operands()
+        .into_iter()
+        .map(|operand| {
+            let i = evaluate_node(operand, depth + 1, nodes);
+            nodes[i].voxels.clone()
+        })
+        .collect();
+    nodes[index].voxels = obj.combine(operands);
+    index
+}
+
+/// Prints the tree with one numbered node per line, indented by depth, with
+/// its voxel count and bounding box.
+pub fn format_tree(nodes: &[DumpNode]) -> String {
+    let mut lines = Vec::with_capacity(nodes.len());
+    for (i, node) in nodes.iter().enumerate() {
+        let mut line = format!(
+            "{}#{} {}: {} voxels",
+            "  ".repeat(node.depth),
+            i,
+            label(node.obj),
+            node.voxels.len()
+        );
+        if !node.voxels.is_empty() {
+            let min = node.voxels.iter().fold(IVec3::MAX, |acc, &v| acc.min(v));
+            let max = node.voxels.iter().fold(IVec3::MIN, |acc, &v| acc.max(v));
+            line.push_str(&format!(", bounds {}..{}", min, max + IVec3::ONE));
+        }
+        lines.push(line);
+    }
+    lines.join("\n") + "\n"
+}
+
+fn label(obj: &GeometryObject) -> String {
+    match obj {
+        GeometryObject::Cube(cube) => format!("Cube {}+{}", cube.corner, cube.size),
+        GeometryObject::Intersection(..) => "Intersection".to_string(),
+        GeometryObject::Union(..) => "Union".to_string(),
+        GeometryObject::Minus(..) => "Minus".to_string(),
+        GeometryObject::SymmetricDifference(..) => "SymmetricDifference".to_string(),
+        GeometryObject::Wireframe(_, n) => format!("Wireframe({})", format_neighbourhood(n)),
+        GeometryObject::Hull(_, n) => format!("Hull({})", format_neighbourhood(n)),
+        GeometryObject::Grow(_, n) => format!("Grow({})", format_neighbourhood(n)),
+        GeometryObject::Shrink(_, n) => format!("Shrink({})", format_neighbourhood(n)),
+        GeometryObject::Open(_, n) => format!("Open({})", format_neighbourhood(n)),
+        GeometryObject::Close(_, n) => format!("Close({})", format_neighbourhood(n)),
+        GeometryObject::Shell(_, n, open) => match open {
+            Some(dir) => format!("Shell({}, open={})", format_neighbourhood(n), dir),
+            None => format!("Shell({})", format_neighbourhood(n)),
+        },
+        GeometryObject::Repeat(_, steps) => {
+            let steps = steps
+                .iter()
+                .map(|(step, count)| format!("{}x{}", step, count))
+                .collect::<Vec<_>>();
+            format!("Repeat({})", steps.join(", "))
+        }
+        GeometryObject::Crop(_, bounds) => format!("Crop({})", format_bounds(bounds)),
+        GeometryObject::Scale(_, factor) => format!("Scale({})", factor),
+    }
+}
+
+fn format_neighbourhood(neighbourhood: &Neighbourhood) -> String {
+    let kind = match neighbourhood.kind {
+        NeighbourhoodKind::Faces => "faces",
+        NeighbourhoodKind::Edges => "edges",
+        NeighbourhoodKind::Corners => "corners",
+        NeighbourhoodKind::Sphere => "sphere",
+    };
+    format!(
+        "r={}, {}, axes={}",
+        neighbourhood.radius, kind, neighbourhood.axes
+    )
+}
+
+/// Prints the bounds per axis, leaving out unbounded sides.
+fn format_bounds(bounds: &Bounds) -> String {
+    let axes = ["x", "y", "z"]
+        .iter()
+        .enumerate()
+        .map(|(i, axis)| {
+            let min = Some(bounds.min[i]).filter(|&v| v != i32::MIN);
+            let max = Some(bounds.max[i]).filter(|&v| v != i32::MAX);
+            let format = |v: Option<i32>| v.map(|v| v.to_string()).unwrap_or_default();
+            format!("{}={}..{}", axis, format(min), format(max))
+        })
+        .collect::<Vec<_>>();
+    axes.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::cube;
+
+    #[test]
+    fn dumps_nodes_in_file_order() {
+        let obj = GeometryObject::Minus(
+            Box::new(GeometryObject::Grow(
+                Box::new(cube(IVec3::ONE, IVec3::ONE)),
+                Neighbourhood::default(),
+            )),
+            Box::new(GeometryObject::Crop(
+                Box::new(cube(IVec3::ZERO, IVec3::splat(3))),
+                Bounds {
+                    max: IVec3::new(i32::MAX, 1, i32::MAX),
+                    ..Bounds::ALL
+                },
+            )),
+        );
+        let nodes = evaluate_nodes(&obj);
+        let depths: Vec<_> = nodes.iter().map(|n| n.depth).collect();
+        assert_eq!(depths, [0, 1, 2, 1, 2]);
+        let counts: Vec<_> = nodes.iter().map(|n| n.voxels.len()).collect();
+        assert_eq!(counts, [18, 27, 1, 9, 27]);
+
+        let tree = format_tree(&nodes);
+        let lines: Vec<_> = tree.lines().collect();
+        assert_eq!(lines[0], "#0 Minus: 18 voxels, bounds [0, 1, 0]..[3, 3, 3]");
+        assert_eq!(
+            lines[3],
+            "  #3 Crop(x=.., y=..1, z=..): 9 voxels, bounds [0, 0, 0]..[3, 1, 3]"
+        );
+        assert_eq!(
+            lines[4],
+            "    #4 Cube [0, 0, 0]+[3, 3, 3]: 27 voxels, bounds [0, 0, 0]..[3, 3, 3]"
+        );
+    }
+}
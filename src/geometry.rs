@@ -3,7 +3,8 @@ use std::collections::HashSet;
 use bevy_math::{IVec3, Vec2, Vec3};
 
 use crate::app::{Cube, FileContent};
-use anyhow::{Result, ensure};
+use anyhow::{Result, anyhow, ensure};
+use serde::Deserialize;
 
 #[derive(Debug)]
 pub struct Quad {
@@ -32,14 +33,17 @@ impl Quad {
         }
     }
 
-    fn get_normal(vertices: [IVec3; 4]) -> Vec3 {
+    pub fn get_normal(vertices: [IVec3; 4]) -> Vec3 {
         let normal = IVec3::cross(vertices[1] - vertices[0], vertices[2] - vertices[0]).as_vec3();
         normal / normal.length()
     }
 }
 
 pub fn convert_to_geometry(content: &FileContent) -> Result<Geometry> {
-    let voxels: Vec<_> = content.obj.get_voxels().into_iter().collect();
+    geometry_from_voxels(&content.obj.get_voxels())
+}
+
+pub fn geometry_from_voxels(voxels: &HashSet<IVec3>) -> Result<Geometry> {
     ensure!(!voxels.is_empty(), "No voxels found");
 
     let min = voxels.iter().fold(IVec3::MAX, |acc, &v| IVec3::min(acc, v));
@@ -53,13 +57,47 @@ pub fn convert_to_geometry(content: &FileContent) -> Result<Geometry> {
     }
 
     Ok(Geometry {
-        resolution: content.resolution,
         min,
         size,
         voxels: grid,
     })
 }
 
+/// A box covering `corner..corner + size` on every axis, for tests. The end
+/// is exclusive, as with `Bounds::max`.
+#[cfg(test)]
+pub fn cube(corner: IVec3, size: IVec3) -> GeometryObject {
+    GeometryObject::Cube(Cube { corner, size })
+}
+
+/// A model of the object at resolution 1 without scaling, for tests.
+#[cfg(test)]
+pub fn content_of(obj: GeometryObject) -> FileContent {
+    FileContent {
+        resolution: 1,
+        origin: Vec3::ZERO,
+        scale: Vec3::ONE,
+        unit: 1.0,
+        obj,
+    }
+}
+
+/// The quads of the object, without uvs, for tests.
+#[cfg(test)]
+pub fn quads_of(obj: GeometryObject) -> Vec<Quad> {
+    generate_quads(&convert_to_geometry(&content_of(obj)).unwrap())
+}
+
+/// The quads of the object with their uvs applied, along with the packed
+/// texture they map into, for tests.
+#[cfg(test)]
+pub fn textured_quads_of(obj: GeometryObject) -> (Vec<Quad>, Vec<crate::texture::TexQuad>, usize) {
+    let mut quads = quads_of(obj);
+    let (tex_quads, size) = crate::texture::pack_quad_texture(&quads);
+    crate::texture::apply_uv_to_quads(&mut quads, &tex_quads, size);
+    (quads, tex_quads, size)
+}
+
 pub fn generate_quads(geometry: &Geometry) -> Vec<Quad> {
     let mut quads = Vec::new();
     let slices = vec![
@@ -115,7 +153,7 @@ pub fn generate_quads(geometry: &Geometry) -> Vec<Quad> {
     for (steps, origin, normal, dir1, dir2, offset) in slices {
         for n in 0..steps {
             let pos = origin + n * normal;
-            let quads_slice = generate_quads_slice(&geometry, pos, dir1, dir2, normal, offset);
+            let quads_slice = generate_quads_slice(geometry, pos, dir1, dir2, normal, offset);
             quads.extend(quads_slice);
         }
     }
@@ -210,73 +248,190 @@ fn get_slice_mask(
 
 #[derive(Debug)]
 pub struct Geometry {
-    pub resolution: i32,
     pub min: IVec3,
     pub size: IVec3,
     pub voxels: Vec<Vec<Vec<bool>>>,
 }
 
-#[derive(Debug)]
+impl Geometry {
+    pub fn is_solid(&self, pos: IVec3) -> bool {
+        let p = pos - self.min;
+        if p.cmplt(IVec3::ZERO).any() || p.cmpge(self.size).any() {
+            return false;
+        }
+        self.voxels[p.x as usize][p.y as usize][p.z as usize]
+    }
+
+    /// The positions of the solid voxels.
+    pub fn solid_voxels(&self) -> HashSet<IVec3> {
+        let mut voxels = HashSet::new();
+        for (x, plane) in self.voxels.iter().enumerate() {
+            for (y, row) in plane.iter().enumerate() {
+                for (z, solid) in row.iter().enumerate() {
+                    if *solid {
+                        voxels.insert(self.min + IVec3::new(x as i32, y as i32, z as i32));
+                    }
+                }
+            }
+        }
+        voxels
+    }
+
+    /// Fills the empty voxels that air from outside the bounding box cannot
+    /// reach through faces, so that no faces are generated around enclosed
+    /// cavities. Returns the number of filled voxels.
+    pub fn fill_cavities(&mut self) -> usize {
+        // The reachable air, in a grid with one layer of padding around the
+        // voxels so that the outside is connected.
+        let padded = self.size + IVec3::splat(2);
+        let index = |p: IVec3| ((p.x * padded.y + p.y) * padded.z + p.z) as usize;
+        let mut reached = vec![false; padded.element_product() as usize];
+        let mut stack = vec![IVec3::ZERO];
+        reached[0] = true;
+        while let Some(pos) = stack.pop() {
+            for dir in [IVec3::X, IVec3::Y, IVec3::Z] {
+                for next in [pos + dir, pos - dir] {
+                    if next.cmplt(IVec3::ZERO).any() || next.cmpge(padded).any() {
+                        continue;
+                    }
+                    if reached[index(next)] || self.is_solid(next - IVec3::ONE + self.min) {
+                        continue;
+                    }
+                    reached[index(next)] = true;
+                    stack.push(next);
+                }
+            }
+        }
+
+        let mut filled = 0;
+        for (x, plane) in self.voxels.iter_mut().enumerate() {
+            for (y, row) in plane.iter_mut().enumerate() {
+                for (z, solid) in row.iter_mut().enumerate() {
+                    let p = IVec3::new(x as i32, y as i32, z as i32) + IVec3::ONE;
+                    if !*solid && !reached[index(p)] {
+                        *solid = true;
+                        filled += 1;
+                    }
+                }
+            }
+        }
+        filled
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum GeometryObject {
     Cube(Cube),
     Intersection(Box<GeometryObject>, Box<GeometryObject>),
     Union(Box<GeometryObject>, Box<GeometryObject>),
     Minus(Box<GeometryObject>, Box<GeometryObject>),
     SymmetricDifference(Box<GeometryObject>, Box<GeometryObject>),
-    Wireframe(Box<GeometryObject>),
-    Hull(Box<GeometryObject>),
-    Grow(Box<GeometryObject>),
+    Wireframe(Box<GeometryObject>, Neighbourhood),
+    Hull(Box<GeometryObject>, Neighbourhood),
+    Grow(Box<GeometryObject>, Neighbourhood),
+    Shrink(Box<GeometryObject>, Neighbourhood),
+    Open(Box<GeometryObject>, Neighbourhood),
+    Close(Box<GeometryObject>, Neighbourhood),
+    /// Hollows out the object, leaving walls as thick as the neighbourhood
+    /// radius. The cavity can be opened towards the given direction.
+    Shell(Box<GeometryObject>, Neighbourhood, Option<IVec3>),
+    /// Unions copies of the object, translated by every combination of the
+    /// given steps repeated up to their counts.
+    Repeat(Box<GeometryObject>, Vec<(IVec3, i32)>),
+    /// Keeps the voxels inside the bounds, which may be open on some sides to
+    /// cut the object along a plane.
+    Crop(Box<GeometryObject>, Bounds),
+    /// Replaces every voxel by a cube of the given size, used to include
+    /// models of a lower resolution.
+    Scale(Box<GeometryObject>, i32),
 }
 
 impl GeometryObject {
-    pub fn get_voxels(&self) -> HashSet<IVec3> {
+    pub fn operands(&self) -> Vec<&GeometryObject> {
         match self {
-            GeometryObject::Cube(cube) => {
-                let mut voxels: HashSet<_> = HashSet::new();
-                for x in 0..cube.size.x {
-                    for y in 0..cube.size.y {
-                        for z in 0..cube.size.z {
-                            voxels.insert(cube.corner + IVec3::new(x, y, z));
-                        }
-                    }
-                }
-                voxels
+            GeometryObject::Cube(_) => Vec::new(),
+            GeometryObject::Intersection(a, b)
+            | GeometryObject::Union(a, b)
+            | GeometryObject::Minus(a, b)
+            | GeometryObject::SymmetricDifference(a, b) => vec![a, b],
+            GeometryObject::Wireframe(a, _)
+            | GeometryObject::Hull(a, _)
+            | GeometryObject::Grow(a, _)
+            | GeometryObject::Shrink(a, _)
+            | GeometryObject::Open(a, _)
+            | GeometryObject::Close(a, _)
+            | GeometryObject::Shell(a, _, _)
+            | GeometryObject::Repeat(a, _)
+            | GeometryObject::Crop(a, _)
+            | GeometryObject::Scale(a, _) => vec![a],
+        }
+    }
+
+    pub fn get_voxels(&self) -> HashSet<IVec3> {
+        self.get_voxels_within(&Bounds::ALL)
+    }
+
+    /// Evaluates the voxels inside the bounds. Cubes, set operations and crops
+    /// skip everything outside early, while the other operators depend on
+    /// voxels outside the bounds and are filtered afterwards.
+    fn get_voxels_within(&self, bounds: &Bounds) -> HashSet<IVec3> {
+        let mut voxels = match self {
+            GeometryObject::Cube(cube) => get_cube(cube, bounds),
+            GeometryObject::Intersection(..)
+            | GeometryObject::Union(..)
+            | GeometryObject::Minus(..)
+            | GeometryObject::SymmetricDifference(..) => self.combine(
+                self.operands()
+                    .into_iter()
+                    .map(|operand| operand.get_voxels_within(bounds))
+                    .collect(),
+            ),
+            GeometryObject::Crop(geometry_object, crop) => {
+                geometry_object.get_voxels_within(&bounds.intersect(crop))
             }
-            GeometryObject::Intersection(geometry_object, geometry_object1) => {
-                let voxels = geometry_object.get_voxels();
-                let voxels1 = geometry_object1.get_voxels();
+            _ => self.combine(
+                self.operands()
+                    .into_iter()
+                    .map(GeometryObject::get_voxels)
+                    .collect(),
+            ),
+        };
+        if *bounds != Bounds::ALL {
+            voxels.retain(|voxel| bounds.contains(*voxel));
+        }
+        voxels
+    }
+
+    /// Computes the voxels of this node from the voxels of its operands, in
+    /// the order of `operands`.
+    pub fn combine(&self, operands: Vec<HashSet<IVec3>>) -> HashSet<IVec3> {
+        let mut operands = operands.into_iter();
+        let mut operand = || operands.next().expect("Missing operand");
+        match self {
+            GeometryObject::Cube(cube) => get_cube(cube, &Bounds::ALL),
+            GeometryObject::Intersection(..) => {
+                let (voxels, voxels1) = (operand(), operand());
                 voxels.intersection(&voxels1).cloned().collect()
             }
-            GeometryObject::Union(geometry_object, geometry_object1) => {
-                let voxels = geometry_object.get_voxels();
-                let voxels1 = geometry_object1.get_voxels();
+            GeometryObject::Union(..) => {
+                let (voxels, voxels1) = (operand(), operand());
                 voxels.union(&voxels1).cloned().collect()
             }
-            GeometryObject::Minus(geometry_object, geometry_object1) => {
-                let voxels = geometry_object.get_voxels();
-                let voxels1 = geometry_object1.get_voxels();
+            GeometryObject::Minus(..) => {
+                let (voxels, voxels1) = (operand(), operand());
                 voxels.difference(&voxels1).cloned().collect()
             }
-            GeometryObject::SymmetricDifference(geometry_object, geometry_object1) => {
-                let voxels = geometry_object.get_voxels();
-                let voxels1 = geometry_object1.get_voxels();
+            GeometryObject::SymmetricDifference(..) => {
+                let (voxels, voxels1) = (operand(), operand());
                 voxels.symmetric_difference(&voxels1).cloned().collect()
             }
-            GeometryObject::Wireframe(geometry_object) => {
-                let voxels = geometry_object.get_voxels();
-                let mut hull_voxels = HashSet::new();
-                for voxel in voxels.iter() {
-                    for x in -1..=1i32 {
-                        for y in -1..=1i32 {
-                            for z in -1..=1i32 {
-                                let neighbor = *voxel + IVec3::new(x, y, z);
-                                if !voxels.contains(&neighbor) {
-                                    hull_voxels.insert(*voxel);
-                                }
-                            }
-                        }
-                    }
-                }
+            GeometryObject::Wireframe(_, neighbourhood) => {
+                let voxels = operand();
+                let surface = Neighbourhood {
+                    radius: 1,
+                    ..*neighbourhood
+                };
+                let hull_voxels = get_hull(&voxels, &surface.offsets());
                 let mut wireframe_voxels = HashSet::new();
                 for voxel in &hull_voxels {
                     let mut dir_count = 0;
@@ -297,42 +452,730 @@ impl GeometryObject {
                         }
                     }
                     if dir_count < 2 || (dir_count == 2 && half_dir_count == 1) {
-                        wireframe_voxels.insert(voxel.clone());
+                        wireframe_voxels.insert(*voxel);
                     }
                 }
+                if neighbourhood.radius > 1 {
+                    let thickness = Neighbourhood {
+                        radius: neighbourhood.radius - 1,
+                        ..*neighbourhood
+                    };
+                    let thick_voxels = get_grown(&wireframe_voxels, &thickness.offsets());
+                    wireframe_voxels = thick_voxels.intersection(&voxels).cloned().collect();
+                }
                 wireframe_voxels
             }
-            GeometryObject::Hull(geometry_object) => {
-                let voxels = geometry_object.get_voxels();
-                let mut hull_voxels = HashSet::new();
-                for voxel in voxels.iter() {
-                    for x in -1..=1i32 {
-                        for y in -1..=1i32 {
-                            for z in -1..=1i32 {
-                                let neighbor = *voxel + IVec3::new(x, y, z);
-                                if !voxels.contains(&neighbor) {
-                                    hull_voxels.insert(*voxel);
-                                }
-                            }
-                        }
+            GeometryObject::Hull(_, neighbourhood) => {
+                let voxels = operand();
+                get_hull(&voxels, &neighbourhood.offsets())
+            }
+            GeometryObject::Grow(_, neighbourhood) => {
+                let voxels = operand();
+                get_grown(&voxels, &neighbourhood.offsets())
+            }
+            GeometryObject::Shrink(_, neighbourhood) => {
+                let voxels = operand();
+                get_shrunk(&voxels, &neighbourhood.offsets())
+            }
+            GeometryObject::Open(_, neighbourhood) => {
+                let voxels = operand();
+                let offsets = neighbourhood.offsets();
+                get_grown(&get_shrunk(&voxels, &offsets), &offsets)
+            }
+            GeometryObject::Close(_, neighbourhood) => {
+                let voxels = operand();
+                let offsets = neighbourhood.offsets();
+                get_shrunk(&get_grown(&voxels, &offsets), &offsets)
+            }
+            GeometryObject::Shell(_, neighbourhood, open) => {
+                let voxels = operand();
+                let mut offsets = neighbourhood.offsets();
+                if let Some(dir) = open {
+                    // Ignoring the neighbours on the open side lets the cavity
+                    // reach through that wall.
+                    offsets.retain(|offset| offset.dot(*dir) <= 0);
+                }
+                let cavity = get_shrunk(&voxels, &offsets);
+                voxels.difference(&cavity).cloned().collect()
+            }
+            GeometryObject::Repeat(_, steps) => {
+                let mut voxels = operand();
+                for (step, count) in steps {
+                    let mut repeated_voxels = HashSet::new();
+                    for i in 0..*count {
+                        repeated_voxels.extend(voxels.iter().map(|v| *v + i * *step));
                     }
+                    voxels = repeated_voxels;
                 }
-                hull_voxels
+                voxels
             }
-            GeometryObject::Grow(geometry_object) => {
-                let voxels = geometry_object.get_voxels();
-                let mut grown_voxels = HashSet::new();
+            GeometryObject::Scale(_, factor) => {
+                let voxels = operand();
+                let mut scaled_voxels = HashSet::new();
                 for voxel in voxels.iter() {
-                    for x in -1..=1i32 {
-                        for y in -1..=1i32 {
-                            for z in -1..=1i32 {
-                                let neighbor = *voxel + IVec3::new(x, y, z);
-                                grown_voxels.insert(neighbor);
+                    for x in 0..*factor {
+                        for y in 0..*factor {
+                            for z in 0..*factor {
+                                scaled_voxels.insert(*voxel * *factor + IVec3::new(x, y, z));
                             }
                         }
                     }
                 }
-                grown_voxels
+                scaled_voxels
+            }
+            GeometryObject::Crop(_, crop) => {
+                let mut voxels = operand();
+                voxels.retain(|voxel| crop.contains(*voxel));
+                voxels
+            }
+        }
+    }
+}
+
+fn get_cube(cube: &Cube, bounds: &Bounds) -> HashSet<IVec3> {
+    let min = cube.corner.max(bounds.min);
+    let max = (cube.corner + cube.size).min(bounds.max);
+    let mut voxels = HashSet::new();
+    for x in min.x..max.x {
+        for y in min.y..max.y {
+            for z in min.z..max.z {
+                voxels.insert(IVec3::new(x, y, z));
+            }
+        }
+    }
+    voxels
+}
+
+/// An axis-aligned box from `min` (inclusive) to `max` (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: IVec3,
+    pub max: IVec3,
+}
+
+impl Bounds {
+    pub const ALL: Self = Self {
+        min: IVec3::MIN,
+        max: IVec3::MAX,
+    };
+
+    pub fn contains(&self, pos: IVec3) -> bool {
+        pos.cmpge(self.min).all() && pos.cmplt(self.max).all()
+    }
+
+    pub fn intersect(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: self.min.max(other.min),
+            max: self.max.min(other.max),
+        }
+    }
+}
+
+/// Keeps the voxels that have an empty neighbour.
+fn get_hull(voxels: &HashSet<IVec3>, offsets: &[IVec3]) -> HashSet<IVec3> {
+    let mut hull_voxels = HashSet::new();
+    for voxel in voxels.iter() {
+        if offsets
+            .iter()
+            .any(|offset| !voxels.contains(&(*voxel + *offset)))
+        {
+            hull_voxels.insert(*voxel);
+        }
+    }
+    hull_voxels
+}
+
+/// Adds all neighbours of the voxels.
+fn get_grown(voxels: &HashSet<IVec3>, offsets: &[IVec3]) -> HashSet<IVec3> {
+    let mut grown_voxels = HashSet::new();
+    for voxel in voxels.iter() {
+        for offset in offsets {
+            grown_voxels.insert(*voxel + *offset);
+        }
+    }
+    grown_voxels
+}
+
+/// Keeps the voxels whose neighbours are all solid.
+fn get_shrunk(voxels: &HashSet<IVec3>, offsets: &[IVec3]) -> HashSet<IVec3> {
+    let mut shrunk_voxels = HashSet::new();
+    for voxel in voxels.iter() {
+        if offsets
+            .iter()
+            .all(|offset| voxels.contains(&(*voxel + *offset)))
+        {
+            shrunk_voxels.insert(*voxel);
+        }
+    }
+    shrunk_voxels
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NeighbourhoodKind {
+    /// Neighbours within the given Manhattan distance, 6 at radius 1.
+    Faces,
+    /// Like `Corners`, but without offsets whose Manhattan distance exceeds
+    /// twice the radius, 18 at radius 1.
+    Edges,
+    /// Neighbours within the given Chebyshev distance, 26 at radius 1.
+    Corners,
+    /// Neighbours within the given Euclidean distance.
+    Sphere,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Neighbourhood {
+    pub radius: i32,
+    pub kind: NeighbourhoodKind,
+    /// Mask of the axes the neighbourhood extends along, 1 for enabled axes.
+    pub axes: IVec3,
+}
+
+impl Default for Neighbourhood {
+    fn default() -> Self {
+        Self {
+            radius: 1,
+            kind: NeighbourhoodKind::Corners,
+            axes: IVec3::ONE,
+        }
+    }
+}
+
+impl Neighbourhood {
+    /// The largest radius accepted from model files, keeping the number of
+    /// offsets below 300,000.
+    pub const MAX_RADIUS: i32 = 32;
+
+    /// Returns the offsets of all neighbours, including the zero offset.
+    pub fn offsets(&self) -> Vec<IVec3> {
+        let r = self.radius * self.axes;
+        let mut offsets = Vec::new();
+        for x in -r.x..=r.x {
+            for y in -r.y..=r.y {
+                for z in -r.z..=r.z {
+                    let offset = IVec3::new(x, y, z);
+                    let abs = offset.abs();
+                    let included = match self.kind {
+                        NeighbourhoodKind::Faces => abs.element_sum() <= self.radius,
+                        NeighbourhoodKind::Edges => abs.element_sum() <= 2 * self.radius,
+                        NeighbourhoodKind::Corners => true,
+                        NeighbourhoodKind::Sphere => offset.length_squared() <= self.radius.pow(2),
+                    };
+                    if included {
+                        offsets.push(offset);
+                    }
+                }
+            }
+        }
+        offsets
+    }
+}
+
+/// The largest count of a single repeat step accepted from model files.
+pub const MAX_REPEAT_COUNT: i32 = 1024;
+
+/// Checks the counts of a repeat and that the largest offset of any copy
+/// fits in an `i32`.
+pub fn check_repeat_steps(steps: &[(IVec3, i32)]) -> Result<()> {
+    let mut total = IVec3::ZERO;
+    for &(step, count) in steps {
+        ensure!(count > 0, "Invalid count");
+        ensure!(
+            count <= MAX_REPEAT_COUNT,
+            "Count {} is too large, the largest is {}",
+            count,
+            MAX_REPEAT_COUNT
+        );
+        for axis in 0..3 {
+            total[axis] = step[axis]
+                .checked_abs()
+                .and_then(|v| v.checked_mul(count))
+                .and_then(|v| v.checked_add(total[axis]))
+                .ok_or_else(|| anyhow!("Repeat offset overflows: {} x {}", step, count))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(corner: IVec3) -> GeometryObject {
+        cube(corner, IVec3::ONE)
+    }
+
+    fn set(voxels: &[IVec3]) -> HashSet<IVec3> {
+        voxels.iter().copied().collect()
+    }
+
+    #[test]
+    fn cube_voxels() {
+        let voxels = cube(IVec3::new(-1, 2, 3), IVec3::new(2, 3, 4)).get_voxels();
+        assert_eq!(voxels.len(), 24);
+        assert!(voxels.contains(&IVec3::new(-1, 2, 3)));
+        assert!(voxels.contains(&IVec3::new(0, 4, 6)));
+        assert!(!voxels.contains(&IVec3::new(1, 4, 6)));
+    }
+
+    #[test]
+    fn set_operation_voxels() {
+        let a = || Box::new(cube(IVec3::ZERO, IVec3::new(2, 1, 1)));
+        let b = || Box::new(cube(IVec3::X, IVec3::new(2, 1, 1)));
+        let (x0, x1, x2) = (IVec3::ZERO, IVec3::X, IVec3::new(2, 0, 0));
+
+        let intersection = GeometryObject::Intersection(a(), b()).get_voxels();
+        assert_eq!(intersection, set(&[x1]));
+        let union = GeometryObject::Union(a(), b()).get_voxels();
+        assert_eq!(union, set(&[x0, x1, x2]));
+        let minus = GeometryObject::Minus(a(), b()).get_voxels();
+        assert_eq!(minus, set(&[x0]));
+        let difference = GeometryObject::SymmetricDifference(a(), b()).get_voxels();
+        assert_eq!(difference, set(&[x0, x2]));
+    }
+
+    #[test]
+    fn wireframe_keeps_edges_of_cube() {
+        let voxels = GeometryObject::Wireframe(
+            Box::new(cube(IVec3::ZERO, IVec3::splat(3))),
+            Neighbourhood::default(),
+        );
+        let voxels = voxels.get_voxels();
+        assert_eq!(voxels.len(), 20);
+        assert!(voxels.contains(&IVec3::ZERO));
+        assert!(voxels.contains(&IVec3::new(1, 0, 0)));
+        assert!(!voxels.contains(&IVec3::new(1, 1, 0)));
+        assert!(!voxels.contains(&IVec3::ONE));
+    }
+
+    #[test]
+    fn hull_removes_interior() {
+        let voxels = GeometryObject::Hull(
+            Box::new(cube(IVec3::ZERO, IVec3::splat(3))),
+            Neighbourhood::default(),
+        );
+        let voxels = voxels.get_voxels();
+        assert_eq!(voxels.len(), 26);
+        assert!(!voxels.contains(&IVec3::ONE));
+    }
+
+    #[test]
+    fn grow_adds_all_neighbours() {
+        let voxels = GeometryObject::Grow(Box::new(unit(IVec3::ZERO)), Neighbourhood::default())
+            .get_voxels();
+        assert_eq!(voxels, cube(-IVec3::ONE, IVec3::splat(3)).get_voxels());
+    }
+
+    #[test]
+    fn neighbourhood_sizes() {
+        let count = |radius, kind, axes| {
+            let neighbourhood = Neighbourhood { radius, kind, axes };
+            neighbourhood.offsets().len() - 1
+        };
+        assert_eq!(count(1, NeighbourhoodKind::Faces, IVec3::ONE), 6);
+        assert_eq!(count(1, NeighbourhoodKind::Edges, IVec3::ONE), 18);
+        assert_eq!(count(1, NeighbourhoodKind::Corners, IVec3::ONE), 26);
+        assert_eq!(count(1, NeighbourhoodKind::Sphere, IVec3::ONE), 6);
+        assert_eq!(count(2, NeighbourhoodKind::Sphere, IVec3::ONE), 32);
+        assert_eq!(count(2, NeighbourhoodKind::Corners, IVec3::ONE), 124);
+        assert_eq!(count(1, NeighbourhoodKind::Corners, IVec3::new(1, 0, 1)), 8);
+        assert_eq!(count(3, NeighbourhoodKind::Faces, IVec3::Y), 6);
+    }
+
+    #[test]
+    fn grow_with_radius_and_axes() {
+        let neighbourhood = Neighbourhood {
+            radius: 2,
+            kind: NeighbourhoodKind::Corners,
+            axes: IVec3::new(1, 0, 1),
+        };
+        let voxels = GeometryObject::Grow(Box::new(unit(IVec3::ZERO)), neighbourhood);
+        let expected = cube(IVec3::new(-2, 0, -2), IVec3::new(5, 1, 5));
+        assert_eq!(voxels.get_voxels(), expected.get_voxels());
+    }
+
+    #[test]
+    fn hull_with_radius_keeps_thick_walls() {
+        let neighbourhood = Neighbourhood {
+            radius: 2,
+            ..Default::default()
+        };
+        let voxels =
+            GeometryObject::Hull(Box::new(cube(IVec3::ZERO, IVec3::splat(5))), neighbourhood);
+        assert_eq!(voxels.get_voxels().len(), 125 - 1);
+    }
+
+    #[test]
+    fn face_hull_ignores_diagonal_gaps() {
+        // The inner corner voxel of an L only borders empty space diagonally.
+        let l_shape = || {
+            Box::new(GeometryObject::Minus(
+                Box::new(cube(IVec3::ZERO, IVec3::splat(3))),
+                Box::new(cube(IVec3::new(2, 2, 0), IVec3::new(1, 1, 3))),
+            ))
+        };
+        let faces = Neighbourhood {
+            kind: NeighbourhoodKind::Faces,
+            ..Default::default()
+        };
+        let corner = IVec3::ONE;
+        let hull = GeometryObject::Hull(l_shape(), Neighbourhood::default()).get_voxels();
+        assert!(hull.contains(&corner));
+        let hull = GeometryObject::Hull(l_shape(), faces).get_voxels();
+        assert!(!hull.contains(&corner));
+    }
+
+    #[test]
+    fn hull_along_axes() {
+        let neighbourhood = Neighbourhood {
+            kind: NeighbourhoodKind::Faces,
+            axes: IVec3::new(1, 1, 0),
+            ..Default::default()
+        };
+        let hull =
+            GeometryObject::Hull(Box::new(cube(IVec3::ZERO, IVec3::splat(3))), neighbourhood);
+        assert_eq!(hull.get_voxels().len(), 24);
+    }
+
+    #[test]
+    fn thick_wireframe_stays_inside() {
+        let neighbourhood = Neighbourhood {
+            radius: 2,
+            ..Default::default()
+        };
+        let solid = || Box::new(cube(IVec3::ZERO, IVec3::splat(6)));
+        let thin = GeometryObject::Wireframe(solid(), Neighbourhood::default()).get_voxels();
+        let thick = GeometryObject::Wireframe(solid(), neighbourhood).get_voxels();
+        assert!(thin.is_subset(&thick));
+        assert!(thick.is_subset(&solid().get_voxels()));
+        assert!(thick.contains(&IVec3::new(1, 1, 3)));
+        assert!(!thick.contains(&IVec3::new(2, 2, 3)));
+    }
+
+    #[test]
+    fn shrink_removes_surface() {
+        let voxels = GeometryObject::Shrink(
+            Box::new(cube(IVec3::ZERO, IVec3::new(3, 4, 5))),
+            Neighbourhood::default(),
+        );
+        assert_eq!(
+            voxels.get_voxels(),
+            cube(IVec3::ONE, IVec3::new(1, 2, 3)).get_voxels()
+        );
+    }
+
+    #[test]
+    fn open_removes_spikes() {
+        let block = || cube(IVec3::ZERO, IVec3::splat(3));
+        let spiked = GeometryObject::Union(Box::new(block()), Box::new(unit(IVec3::new(1, 3, 1))));
+        let opened = GeometryObject::Open(Box::new(spiked), Neighbourhood::default());
+        assert_eq!(opened.get_voxels(), block().get_voxels());
+    }
+
+    #[test]
+    fn close_fills_notches() {
+        let block = || cube(IVec3::ZERO, IVec3::splat(3));
+        let notched = GeometryObject::Minus(Box::new(block()), Box::new(unit(IVec3::new(1, 2, 1))));
+        let closed = GeometryObject::Close(Box::new(notched), Neighbourhood::default());
+        assert_eq!(closed.get_voxels(), block().get_voxels());
+    }
+
+    #[test]
+    fn shell_hollows_box() {
+        let block = || Box::new(cube(IVec3::ZERO, IVec3::splat(6)));
+        let shell = GeometryObject::Shell(block(), Neighbourhood::default(), None);
+        let expected = GeometryObject::Minus(block(), Box::new(cube(IVec3::ONE, IVec3::splat(4))));
+        assert_eq!(shell.get_voxels(), expected.get_voxels());
+
+        let thick = Neighbourhood {
+            radius: 2,
+            ..Default::default()
+        };
+        let shell = GeometryObject::Shell(block(), thick, None);
+        assert_eq!(shell.get_voxels().len(), 216 - 8);
+    }
+
+    #[test]
+    fn shell_opens_towards_direction() {
+        let block = || Box::new(cube(IVec3::ZERO, IVec3::splat(4)));
+        let shell = GeometryObject::Shell(block(), Neighbourhood::default(), Some(IVec3::Y));
+        let expected =
+            GeometryObject::Minus(block(), Box::new(cube(IVec3::ONE, IVec3::new(2, 3, 2))));
+        assert_eq!(shell.get_voxels(), expected.get_voxels());
+    }
+
+    #[test]
+    fn repeat_translates_copies() {
+        let repeat =
+            GeometryObject::Repeat(Box::new(unit(IVec3::ZERO)), vec![(IVec3::new(2, 0, 0), 3)]);
+        let expected = [IVec3::ZERO, IVec3::new(2, 0, 0), IVec3::new(4, 0, 0)];
+        assert_eq!(repeat.get_voxels(), set(&expected));
+    }
+
+    #[test]
+    fn repeat_grid() {
+        let repeat = GeometryObject::Repeat(
+            Box::new(cube(IVec3::ZERO, IVec3::new(1, 2, 1))),
+            vec![
+                (IVec3::new(3, 0, 0), 4),
+                (IVec3::new(0, 0, 2), 2),
+                (IVec3::Y, 2),
+            ],
+        );
+        let voxels = repeat.get_voxels();
+        assert_eq!(voxels.len(), 4 * 2 * 3);
+        assert!(voxels.contains(&IVec3::new(9, 2, 2)));
+        assert!(!voxels.contains(&IVec3::new(12, 0, 0)));
+    }
+
+    #[test]
+    fn crop_clips_to_box() {
+        let bounds = Bounds {
+            min: IVec3::new(1, -5, 2),
+            max: IVec3::new(3, 2, 10),
+        };
+        let crop = GeometryObject::Crop(Box::new(cube(IVec3::ZERO, IVec3::splat(4))), bounds);
+        let expected = cube(IVec3::new(1, 0, 2), IVec3::new(2, 2, 2));
+        assert_eq!(crop.get_voxels(), expected.get_voxels());
+    }
+
+    #[test]
+    fn crop_of_neighbourhood_operators_sees_outside_voxels() {
+        let bounds = Bounds {
+            min: IVec3::ZERO,
+            max: IVec3::MAX,
+        };
+        let block = || Box::new(cube(IVec3::splat(-2), IVec3::splat(4)));
+        let crop = GeometryObject::Crop(
+            Box::new(GeometryObject::Hull(block(), Neighbourhood::default())),
+            bounds,
+        );
+        let expected = cube(IVec3::ZERO, IVec3::splat(2)).get_voxels();
+        let expected: HashSet<_> = expected
+            .into_iter()
+            .filter(|v| v.max_element() == 1)
+            .collect();
+        assert_eq!(crop.get_voxels(), expected);
+    }
+
+    #[test]
+    fn single_voxel_has_six_quads() {
+        let quads = quads_of(unit(IVec3::new(3, -2, 1)));
+        assert_eq!(quads.len(), 6);
+        let normals: HashSet<_> = quads.iter().map(|q| q.normal.as_ivec3()).collect();
+        assert_eq!(normals.len(), 6);
+        assert!(quads.iter().all(|q| q.tex_size == (1, 1)));
+    }
+
+    #[test]
+    fn box_is_merged_into_six_quads() {
+        let quads = quads_of(cube(IVec3::ZERO, IVec3::new(2, 1, 1)));
+        assert_eq!(quads.len(), 6);
+        let mut sizes: Vec<_> = quads.iter().map(|q| q.tex_size.0 * q.tex_size.1).collect();
+        sizes.sort();
+        assert_eq!(sizes, [1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn l_shape_quads() {
+        let quads = quads_of(GeometryObject::Union(
+            Box::new(cube(IVec3::ZERO, IVec3::new(2, 1, 1))),
+            Box::new(cube(IVec3::ZERO, IVec3::new(1, 2, 1))),
+        ));
+        assert_eq!(quads.len(), 10);
+        let area: usize = quads.iter().map(|q| q.tex_size.0 * q.tex_size.1).sum();
+        assert_eq!(area, 14);
+    }
+
+    #[test]
+    fn quads_face_outward() {
+        let content = content_of(GeometryObject::Minus(
+            Box::new(cube(IVec3::ZERO, IVec3::splat(3))),
+            Box::new(unit(IVec3::new(1, 1, 2))),
+        ));
+        let geometry = convert_to_geometry(&content).unwrap();
+        for quad in generate_quads(&geometry) {
+            let center = quad.vertices.iter().map(|v| v.as_vec3()).sum::<Vec3>() / 4.0;
+            let inside = (center - quad.normal * 0.5).floor().as_ivec3();
+            let outside = (center + quad.normal * 0.5).floor().as_ivec3();
+            assert!(geometry.is_solid(inside) && !geometry.is_solid(outside));
+        }
+    }
+
+    #[test]
+    fn fills_enclosed_cavities_only() {
+        let hollow = || {
+            GeometryObject::Minus(
+                Box::new(cube(IVec3::ZERO, IVec3::splat(4))),
+                Box::new(cube(IVec3::ONE, IVec3::splat(2))),
+            )
+        };
+        let mut geometry = convert_to_geometry(&content_of(hollow())).unwrap();
+        assert_eq!(generate_quads(&geometry).len(), 12);
+        assert_eq!(geometry.fill_cavities(), 8);
+        assert_eq!(generate_quads(&geometry).len(), 6);
+
+        // Air gets in through a hole in the top, but not through a notch that
+        // only touches the cavity at a corner.
+        let minus = |corner| GeometryObject::Minus(Box::new(hollow()), Box::new(unit(corner)));
+        let mut geometry = convert_to_geometry(&content_of(minus(IVec3::new(1, 3, 1)))).unwrap();
+        let quads = generate_quads(&geometry).len();
+        assert_eq!(geometry.fill_cavities(), 0);
+        assert_eq!(generate_quads(&geometry).len(), quads);
+        let mut geometry = convert_to_geometry(&content_of(minus(IVec3::splat(3)))).unwrap();
+        assert_eq!(geometry.fill_cavities(), 8);
+    }
+
+    #[test]
+    fn empty_geometry_is_rejected() {
+        let obj = GeometryObject::Minus(Box::new(unit(IVec3::ZERO)), Box::new(unit(IVec3::ZERO)));
+        assert!(convert_to_geometry(&content_of(obj)).is_err());
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn arb_cube() -> impl Strategy<Value = GeometryObject> {
+            (
+                prop::array::uniform3(-4..4i32),
+                prop::array::uniform3(1..4i32),
+            )
+                .prop_map(|(corner, size)| cube(IVec3::from(corner), IVec3::from(size)))
+        }
+
+        fn arb_neighbourhood() -> impl Strategy<Value = Neighbourhood> {
+            let kind = prop_oneof![
+                Just(NeighbourhoodKind::Faces),
+                Just(NeighbourhoodKind::Edges),
+                Just(NeighbourhoodKind::Corners),
+                Just(NeighbourhoodKind::Sphere),
+            ];
+            let axes = prop::array::uniform3(0..=1i32)
+                .prop_filter("no axes", |axes| axes != &[0; 3])
+                .prop_map(IVec3::from);
+            (1..3i32, kind, axes).prop_map(|(radius, kind, axes)| Neighbourhood {
+                radius,
+                kind,
+                axes,
+            })
+        }
+
+        fn arb_object() -> impl Strategy<Value = GeometryObject> {
+            arb_cube().prop_recursive(3, 16, 2, |inner| {
+                let pair =
+                    || (inner.clone(), inner.clone()).prop_map(|(a, b)| (Box::new(a), Box::new(b)));
+                prop_oneof![
+                    pair().prop_map(|(a, b)| GeometryObject::Intersection(a, b)),
+                    pair().prop_map(|(a, b)| GeometryObject::Union(a, b)),
+                    pair().prop_map(|(a, b)| GeometryObject::Minus(a, b)),
+                    pair().prop_map(|(a, b)| GeometryObject::SymmetricDifference(a, b)),
+                    (inner.clone(), arb_neighbourhood())
+                        .prop_map(|(a, n)| GeometryObject::Wireframe(Box::new(a), n)),
+                    (inner.clone(), arb_neighbourhood())
+                        .prop_map(|(a, n)| GeometryObject::Hull(Box::new(a), n)),
+                    (inner.clone(), arb_neighbourhood())
+                        .prop_map(|(a, n)| GeometryObject::Grow(Box::new(a), n)),
+                    (inner.clone(), arb_neighbourhood())
+                        .prop_map(|(a, n)| GeometryObject::Shrink(Box::new(a), n)),
+                    (inner.clone(), arb_neighbourhood()).prop_map(|(a, n)| {
+                        GeometryObject::Shell(Box::new(a), n, Some(IVec3::Y))
+                    }),
+                    (inner.clone(), prop::array::uniform3(-3..3i32), 1..3i32).prop_map(
+                        |(a, step, count)| {
+                            GeometryObject::Repeat(Box::new(a), vec![(IVec3::from(step), count)])
+                        }
+                    ),
+                ]
+            })
+        }
+
+        fn exposed_faces(voxels: &HashSet<IVec3>) -> usize {
+            let dirs = [
+                IVec3::X,
+                IVec3::Y,
+                IVec3::Z,
+                -IVec3::X,
+                -IVec3::Y,
+                -IVec3::Z,
+            ];
+            voxels
+                .iter()
+                .flat_map(|v| dirs.map(|d| *v + d))
+                .filter(|n| !voxels.contains(n))
+                .count()
+        }
+
+        proptest! {
+            #[test]
+            fn minus_self_is_empty(a in arb_object()) {
+                let minus = GeometryObject::Minus(Box::new(a.clone()), Box::new(a));
+                prop_assert!(minus.get_voxels().is_empty());
+            }
+
+            #[test]
+            fn union_is_commutative(a in arb_object(), b in arb_object()) {
+                let ab = GeometryObject::Union(Box::new(a.clone()), Box::new(b.clone()));
+                let ba = GeometryObject::Union(Box::new(b), Box::new(a));
+                prop_assert_eq!(ab.get_voxels(), ba.get_voxels());
+            }
+
+            #[test]
+            fn symmetric_difference_is_union_of_differences(a in arb_object(), b in arb_object()) {
+                let difference = GeometryObject::SymmetricDifference(Box::new(a.clone()), Box::new(b.clone()));
+                let union = GeometryObject::Union(
+                    Box::new(GeometryObject::Minus(Box::new(a.clone()), Box::new(b.clone()))),
+                    Box::new(GeometryObject::Minus(Box::new(b), Box::new(a))),
+                );
+                prop_assert_eq!(difference.get_voxels(), union.get_voxels());
+            }
+
+            #[test]
+            fn hull_is_subset(a in arb_object(), n in arb_neighbourhood()) {
+                let hull = GeometryObject::Hull(Box::new(a.clone()), n).get_voxels();
+                prop_assert!(hull.is_subset(&a.get_voxels()));
+            }
+
+            #[test]
+            fn grow_is_superset(a in arb_object(), n in arb_neighbourhood()) {
+                let grown = GeometryObject::Grow(Box::new(a.clone()), n).get_voxels();
+                prop_assert!(a.get_voxels().is_subset(&grown));
+            }
+
+            #[test]
+            fn shrink_open_close_are_ordered(a in arb_object(), n in arb_neighbourhood()) {
+                let voxels = a.get_voxels();
+                let shrunk = GeometryObject::Shrink(Box::new(a.clone()), n).get_voxels();
+                let opened = GeometryObject::Open(Box::new(a.clone()), n).get_voxels();
+                let closed = GeometryObject::Close(Box::new(a), n).get_voxels();
+                prop_assert!(shrunk.is_subset(&opened));
+                prop_assert!(opened.is_subset(&voxels));
+                prop_assert!(voxels.is_subset(&closed));
+            }
+
+            #[test]
+            fn crop_is_intersection_with_box(
+                a in arb_object(),
+                min in prop::array::uniform3(-5..5i32),
+                size in prop::array::uniform3(0..8i32),
+            ) {
+                let bounds = Bounds {
+                    min: IVec3::from(min),
+                    max: IVec3::from(min) + IVec3::from(size),
+                };
+                let crop = GeometryObject::Crop(Box::new(a.clone()), bounds).get_voxels();
+                let expected: HashSet<_> = a.get_voxels().into_iter().filter(|v| bounds.contains(*v)).collect();
+                prop_assert_eq!(crop, expected);
+            }
+
+            #[test]
+            fn quads_cover_exposed_faces(a in arb_object()) {
+                let voxels = a.get_voxels();
+                prop_assume!(!voxels.is_empty());
+                let quads = quads_of(a);
+                let area: usize = quads.iter().map(|q| q.tex_size.0 * q.tex_size.1).sum();
+                prop_assert_eq!(area, exposed_faces(&voxels));
             }
         }
     }
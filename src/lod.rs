@@ -0,0 +1,84 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy_math::IVec3;
+use clap::ValueEnum;
+
+use crate::geometry::Quad;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LodRule {
+    /// Keep a coarse voxel if at least half of its fine voxels are solid. When
+    /// halving the resolution, flat walls one voxel thick survive.
+    #[default]
+    Majority,
+    /// Keep a coarse voxel if any of its fine voxels is solid.
+    Any,
+}
+
+/// Merges every block of `factor` voxels along each axis into one coarse
+/// voxel, at coordinates divided by `factor`.
+pub fn downsample(voxels: &HashSet<IVec3>, factor: i32, rule: LodRule) -> HashSet<IVec3> {
+    let mut counts: HashMap<IVec3, i32> = HashMap::new();
+    for voxel in voxels {
+        *counts
+            .entry(voxel.div_euclid(IVec3::splat(factor)))
+            .or_default() += 1;
+    }
+    let block = factor.pow(3);
+    counts
+        .into_iter()
+        .filter(|&(_, count)| match rule {
+            LodRule::Majority => count * 2 >= block,
+            LodRule::Any => true,
+        })
+        .map(|(voxel, _)| voxel)
+        .collect()
+}
+
+/// Scales the quads of downsampled voxels back onto the original lattice.
+pub fn upscale_quads(quads: &mut [Quad], factor: i32) {
+    for quad in quads {
+        quad.vertices = quad.vertices.map(|v| v * factor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{cube, generate_quads, geometry_from_voxels};
+
+    #[test]
+    fn downsamples_by_rule() {
+        // A slab one voxel thick, and a single voxel below the origin.
+        let mut voxels = cube(IVec3::ZERO, IVec3::new(4, 1, 4)).get_voxels();
+        voxels.insert(IVec3::splat(-1));
+        let majority = downsample(&voxels, 2, LodRule::Majority);
+        assert_eq!(
+            majority,
+            cube(IVec3::ZERO, IVec3::new(2, 1, 2)).get_voxels()
+        );
+        let any = downsample(&voxels, 2, LodRule::Any);
+        assert_eq!(any.len(), 5);
+        assert!(any.contains(&IVec3::splat(-1)));
+
+        assert!(downsample(&voxels, 4, LodRule::Majority).is_empty());
+        assert_eq!(
+            downsample(&majority, 2, LodRule::Majority),
+            HashSet::from([IVec3::ZERO])
+        );
+        assert_eq!(downsample(&voxels, 4, LodRule::Any).len(), 2);
+    }
+
+    #[test]
+    fn upscaled_quads_cover_original_bounds() {
+        let voxels = cube(IVec3::new(-4, 0, 2), IVec3::new(8, 4, 4)).get_voxels();
+        let coarse = downsample(&voxels, 2, LodRule::Majority);
+        let mut quads = generate_quads(&geometry_from_voxels(&coarse).unwrap());
+        upscale_quads(&mut quads, 2);
+        assert_eq!(quads.len(), 6);
+        let vertices = quads.iter().flat_map(|q| q.vertices);
+        let min = vertices.clone().fold(IVec3::MAX, IVec3::min);
+        let max = vertices.fold(IVec3::MIN, IVec3::max);
+        assert_eq!((min, max), (IVec3::new(-4, 0, 2), IVec3::new(4, 4, 6)));
+    }
+}
@@ -1,48 +1,241 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Result, anyhow, ensure};
-use app::app;
-use clap::Parser;
+use app::{AppOptions, app, diff_files, format_file, load_content};
+use clap::{Parser, Subcommand};
+use collider::ColliderFormat;
+use diff::RevisionCopy;
+use lod::LodRule;
+use obj::Axes;
+use sheet::render_contact_sheet;
+use triangulate::Diagonal;
 
 mod app;
+mod collider;
+mod diff;
+mod dump;
 mod geometry;
+mod lod;
 mod obj;
+mod preview;
+mod sheet;
+mod structured;
+mod syntax;
 mod texture;
+mod tjunction;
+mod triangulate;
+mod validate;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short, long)]
     input: Option<PathBuf>,
 
-    #[arg(short, long)]
-    pattern: String,
+    #[arg(short, long, required = true)]
+    pattern: Option<String>,
 
-    #[arg(short, long)]
-    output: PathBuf,
+    #[arg(short, long, required = true)]
+    output: Option<PathBuf>,
 
     #[arg(short, long, default_value = "true")]
     folder: bool,
+
+    /// Emit triangles instead of quads, choosing the split diagonal as given
+    #[arg(short, long, value_enum)]
+    triangulate: Option<Diagonal>,
+
+    /// Split quad edges at every vertex lying on them to avoid T-junctions.
+    /// When triangulating, the split polygons are ear-clipped and the diagonal
+    /// choice is ignored
+    #[arg(long)]
+    fix_t_junctions: bool,
+
+    /// Share vertex positions between faces, splitting the vertices of edges
+    /// where voxels only touch diagonally so that the mesh stays manifold.
+    /// Welding needs the T-junctions fixed first
+    #[arg(long, conflicts_with = "triangulate", requires = "fix_t_junctions")]
+    weld: bool,
+
+    /// Check the generated mesh for defects and fail if any are found
+    #[arg(long)]
+    validate: bool,
+
+    /// Fill empty space that cannot be reached from outside the model, so
+    /// that no faces are generated around enclosed cavities
+    #[arg(long)]
+    fill_cavities: bool,
+
+    /// Write the evaluated geometry tree, with the voxel count and bounding box
+    /// of every node, to `<name>_tree.txt`
+    #[arg(long)]
+    dump: bool,
+
+    /// Also write the voxels of every node of the dumped tree as its own OBJ
+    /// in `<name>_nodes/`
+    #[arg(long, requires = "dump")]
+    dump_objs: bool,
+
+    /// Render the mesh from isometric, front and top views into
+    /// `<name>_preview.png`
+    #[arg(long)]
+    preview: bool,
+
+    /// The size in pixels of every view of the preview
+    #[arg(long, default_value_t = 256, requires = "preview")]
+    preview_size: u32,
+
+    /// Write a single image with a labelled thumbnail of every model to this
+    /// path, relative to the output directory
+    #[arg(long)]
+    contact_sheet: Option<PathBuf>,
+
+    /// The size in pixels of every thumbnail of the contact sheet
+    #[arg(long, default_value_t = 128, requires = "contact_sheet")]
+    thumbnail_size: u32,
+
+    /// The axes of the exported meshes in terms of the model axes, such as
+    /// `+x+y+z`, or `+x-z+y` for Z up. Mirroring axes reverse the winding
+    #[arg(long, default_value = "-x+y+z", allow_hyphen_values = true)]
+    axes: Axes,
+
+    /// Also write `<name>_lod0.obj` and this many further levels of detail,
+    /// each at half the resolution of the one before
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(..=8))]
+    lods: u32,
+
+    /// How to decide whether a downsampled voxel is solid
+    #[arg(long, value_enum, default_value_t = LodRule::Majority)]
+    lod_rule: LodRule,
+
+    /// Decompose the voxels into boxes for collision and write them in the
+    /// given formats, such as `json,obj`
+    #[arg(long, value_enum, value_delimiter = ',')]
+    box_colliders: Vec<ColliderFormat>,
+
+    /// Write the convex hull of the mesh to `<name>_hull.obj` as a single
+    /// collider
+    #[arg(long)]
+    hull_collider: bool,
+
+    /// The most vertices the convex hull collider may have. Points farthest
+    /// outside the hull are kept first
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(4..), requires = "hull_collider")]
+    hull_max_vertices: u32,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rewrite model files in canonical form
+    Fmt {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Only check that the files are formatted, and fail if not
+        #[arg(long)]
+        check: bool,
+    },
+    /// Compare the voxels of two versions of a model, writing a mesh with
+    /// added voxels in green and removed voxels in red to `<name>_diff.obj`
+    Diff {
+        /// The old version of the model, or with `--rev` the model to compare
+        /// with its own version at that revision
+        file: PathBuf,
+
+        /// The new version of the model
+        #[arg(required_unless_present = "rev", conflicts_with = "rev")]
+        other: Option<PathBuf>,
+
+        /// Compare the file as of this git revision with its current contents
+        #[arg(long)]
+        rev: Option<String>,
+
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+
+        /// Also render the diff mesh into `<name>_diff_preview.png`
+        #[arg(long)]
+        preview: bool,
+
+        /// The size in pixels of every view of the preview
+        #[arg(long, default_value_t = 256, requires = "preview")]
+        preview_size: u32,
+
+        /// The axes of the exported mesh, as for converting models
+        #[arg(long, default_value = "-x+y+z", allow_hyphen_values = true)]
+        axes: Axes,
+    },
 }
 
 fn main() {
     if let Err(err) = run() {
-        eprintln!("Error: {}\n{}", err, err.backtrace());
+        eprintln!("Error: {:#}\n{}", err, err.backtrace());
         std::process::exit(1);
     }
 }
 
 fn run() -> Result<()> {
     let args = Args::parse();
+    match args.command {
+        Some(Command::Fmt { files, check }) => return run_fmt(&files, check),
+        Some(Command::Diff {
+            file,
+            other,
+            rev,
+            output,
+            preview,
+            preview_size,
+            axes,
+        }) => {
+            let preview = preview.then_some(preview_size);
+            let diff = match (other, rev) {
+                (Some(other), _) => {
+                    diff_files(&load_content(&file)?, &other, &output, axes, preview)?
+                }
+                (None, Some(rev)) => {
+                    let copy = RevisionCopy::new(&file, &rev)?;
+                    diff_files(&copy.load()?, &file, &output, axes, preview)?
+                }
+                (None, None) => unreachable!("clap requires another file or a revision"),
+            };
+            println!("{}", diff);
+            return Ok(());
+        }
+        None => {}
+    }
+    let (Some(pattern), Some(output)) = (args.pattern, args.output) else {
+        unreachable!("clap requires a pattern and an output");
+    };
     let input = args.input.unwrap_or_else(|| PathBuf::from("."));
-    let files = glob_input_files(&input, &args.pattern)?;
+    let files = glob_input_files(&input, &pattern)?;
     ensure!(
         !files.is_empty(),
         "No files found in {:?} with pattern {}",
         input,
-        args.pattern
+        pattern
     );
-    let output = args.output;
+    let options = AppOptions {
+        triangulate: args.triangulate,
+        fix_t_junctions: args.fix_t_junctions,
+        weld: args.weld,
+        validate: args.validate,
+        dump: args.dump,
+        dump_objs: args.dump_objs,
+        preview: args.preview.then_some(args.preview_size),
+        thumbnail: args.contact_sheet.as_ref().map(|_| args.thumbnail_size),
+        axes: args.axes,
+        lods: args.lods,
+        lod_rule: args.lod_rule,
+        box_colliders: args.box_colliders,
+        hull_collider: args
+            .hull_collider
+            .then_some(args.hull_max_vertices as usize),
+        fill_cavities: args.fill_cavities,
+    };
+    let mut thumbnails = Vec::new();
     for file in &files {
         let out = if args.folder {
             output.join(file.file_stem().ok_or(anyhow!("Invalid input"))?)
@@ -52,12 +245,40 @@ fn run() -> Result<()> {
         if !out.exists() {
             std::fs::create_dir(&out)?;
         }
-        app(file, &out)?;
+        if let Some(thumbnail) = app(file, &out, &options)? {
+            let stem = file.file_stem().unwrap().to_string_lossy();
+            thumbnails.push((stem.into_owned(), thumbnail));
+        }
+    }
+    if let Some(path) = args.contact_sheet {
+        let sheet = render_contact_sheet(&thumbnails, args.thumbnail_size);
+        sheet.save(output.join(path))?;
+    }
+    Ok(())
+}
+
+fn run_fmt(files: &[PathBuf], check: bool) -> Result<()> {
+    let mut unformatted = 0;
+    for file in files {
+        if !format_file(file, check)? {
+            unformatted += 1;
+            if check {
+                println!("Not formatted: {}", file.display());
+            } else {
+                println!("Formatted: {}", file.display());
+            }
+        }
     }
+    ensure!(
+        !check || unformatted == 0,
+        "{} of {} files are not formatted",
+        unformatted,
+        files.len()
+    );
     Ok(())
 }
 
-fn glob_input_files(input: &PathBuf, pattern: &str) -> Result<Vec<PathBuf>> {
+fn glob_input_files(input: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
     let pattern_path = input.join(pattern);
     let pattern_str = pattern_path.to_str().ok_or(anyhow!("Invalid pattern"))?;
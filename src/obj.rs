@@ -1,14 +1,161 @@
-use std::path::PathBuf;
+use std::{path::Path, str::FromStr};
 
-use bevy_math::Vec3;
+use anyhow::{Result, anyhow, ensure};
+use bevy_math::{IVec3, Vec2, Vec3};
 
-use crate::geometry::Quad;
+use crate::{
+    app::FileContent, geometry::Quad, tjunction::Polygon, triangulate::Triangle,
+    validate::WeldedMesh,
+};
 
-pub fn generate_obj_file(
-    resolution: i32,
-    origin: Vec3,
-    quads: Vec<Quad>,
-    mtl_file_name: &PathBuf,
+/// The axes of the exported mesh in terms of the axes of the model, written
+/// as a signed model axis for each of x, y and z, such as `-x+y+z` or `+x-z+y`
+/// for Z up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Axes {
+    source: [usize; 3],
+    negate: [bool; 3],
+}
+
+impl Default for Axes {
+    fn default() -> Self {
+        Self {
+            source: [0, 1, 2],
+            negate: [true, false, false],
+        }
+    }
+}
+
+impl Axes {
+    pub fn apply(&self, v: Vec3) -> Vec3 {
+        Vec3::from_array(std::array::from_fn(|i| {
+            let value = v[self.source[i]];
+            if self.negate[i] { -value } else { value }
+        }))
+    }
+
+    /// Whether the mapping mirrors the model, which turns the winding of its
+    /// faces around.
+    pub fn mirrors(&self) -> bool {
+        let s = self.source;
+        let inversions = (s[0] > s[1]) as usize + (s[0] > s[2]) as usize + (s[1] > s[2]) as usize;
+        let negations = self.negate.iter().filter(|&&n| n).count();
+        (inversions + negations) % 2 == 1
+    }
+}
+
+impl FromStr for Axes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let chars: Vec<char> = s.chars().collect();
+        ensure!(
+            chars.len() == 6,
+            "Expected a signed axis for each of x, y and z, such as -x+y+z, got {}",
+            s
+        );
+        let mut axes = Axes::default();
+        for (i, pair) in chars.chunks(2).enumerate() {
+            axes.negate[i] = match pair[0] {
+                '+' => false,
+                '-' => true,
+                c => return Err(anyhow!("Expected + or -, got {}", c)),
+            };
+            axes.source[i] = match pair[1].to_ascii_lowercase() {
+                'x' => 0,
+                'y' => 1,
+                'z' => 2,
+                c => return Err(anyhow!("Unknown axis: {}", c)),
+            };
+        }
+        let [a, b, c] = axes.source;
+        ensure!(a != b && a != c && b != c, "Repeated axis in {}", s);
+        Ok(axes)
+    }
+}
+
+/// Maps lattice positions to exported positions: relative to the origin, in
+/// units of the resolution, stretched per axis by the voxel scale and the
+/// output unit, and finally onto the exported axes.
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    pub resolution: i32,
+    pub origin: Vec3,
+    pub scale: Vec3,
+    pub axes: Axes,
+}
+
+impl Transform {
+    pub fn new(content: &FileContent, axes: Axes) -> Self {
+        Self {
+            resolution: content.resolution,
+            origin: content.origin,
+            scale: content.scale * content.unit,
+            axes,
+        }
+    }
+
+    /// The position in output units, still along the axes of the model.
+    pub fn model_position(&self, position: IVec3) -> Vec3 {
+        (position.as_vec3() - self.origin) * self.scale / self.resolution as f32
+    }
+
+    pub fn apply(&self, position: IVec3) -> Vec3 {
+        self.axes.apply(self.model_position(position))
+    }
+
+    pub fn apply_normal(&self, normal: Vec3) -> Vec3 {
+        self.axes.apply(normal)
+    }
+
+    /// The order to write the corners of a face in so that it stays counter
+    /// clockwise seen from outside. Faces are given counter clockwise in the
+    /// model, so mirroring axes reverse them, keeping the first corner.
+    fn corner_order(&self, n: usize) -> impl Iterator<Item = usize> + use<> {
+        let mirrors = self.axes.mirrors();
+        (0..n).map(move |i| if mirrors { (n - i) % n } else { i })
+    }
+}
+
+pub fn generate_obj_file(transform: &Transform, quads: Vec<Quad>, mtl_file_name: &Path) -> String {
+    let faces = quads.iter().map(|quad| {
+        transform
+            .corner_order(4)
+            .map(|i| (quad.vertices[i], quad.normal, quad.uvs[i]))
+    });
+    write_obj_file(transform, faces, mtl_file_name)
+}
+
+pub fn generate_triangle_obj_file(
+    transform: &Transform,
+    triangles: Vec<Triangle>,
+    mtl_file_name: &Path,
+) -> String {
+    let faces = triangles.iter().map(|triangle| {
+        transform
+            .corner_order(3)
+            .map(|i| (triangle.vertices[i], triangle.normal, triangle.uvs[i]))
+    });
+    write_obj_file(transform, faces, mtl_file_name)
+}
+
+pub fn generate_polygon_obj_file(
+    transform: &Transform,
+    polygons: Vec<Polygon>,
+    mtl_file_name: &Path,
+) -> String {
+    let faces = polygons.iter().map(|polygon| {
+        transform
+            .corner_order(polygon.vertices.len())
+            .map(|i| (polygon.vertices[i], polygon.normal, polygon.uvs[i]))
+    });
+    write_obj_file(transform, faces, mtl_file_name)
+}
+
+fn write_obj_file<F: IntoIterator<Item = (IVec3, Vec3, Vec2)>>(
+    transform: &Transform,
+    faces: impl Iterator<Item = F>,
+    mtl_file_name: &Path,
 ) -> String {
     let mut vert_index = 1;
 
@@ -17,17 +164,16 @@ pub fn generate_obj_file(
     let mut uv_lines = Vec::new();
     let mut face_lines = Vec::new();
 
-    for quad in quads {
+    for face in faces {
         let mut face_line = "f".to_string();
-        for i in [0, 3, 2, 1] {
-            let vertex = (quad.vertices[i].as_vec3() - origin) / resolution as f32;
-            let normal = quad.normal;
-            let uvs = quad.uvs[i];
+        for (vertex, normal, uvs) in face {
+            let vertex = transform.apply(vertex);
+            let normal = transform.apply_normal(normal);
 
-            let vertex_line = format!("v {} {} {}", -vertex.x, vertex.y, vertex.z);
+            let vertex_line = format!("v {} {} {}", vertex.x, vertex.y, vertex.z);
             vertex_lines.push(vertex_line);
 
-            let normal_line = format!("vn {} {} {}", -normal.x, normal.y, normal.z);
+            let normal_line = format!("vn {} {} {}", normal.x, normal.y, normal.z);
             normal_lines.push(normal_line);
 
             let uv_line = format!("vt {} {}", uvs.x, uvs.y);
@@ -39,6 +185,95 @@ pub fn generate_obj_file(
         face_lines.push(face_line);
     }
 
+    assemble_obj_file(
+        mtl_file_name,
+        vertex_lines,
+        normal_lines,
+        uv_lines,
+        face_lines,
+    )
+}
+
+/// Writes a mesh whose polygons share their vertex positions, while normals
+/// and uvs stay per corner.
+pub fn generate_welded_obj_file(
+    transform: &Transform,
+    mesh: WeldedMesh,
+    mtl_file_name: &Path,
+) -> String {
+    let mut vertex_lines = Vec::new();
+    let mut normal_lines = Vec::new();
+    let mut uv_lines = Vec::new();
+    let mut face_lines = Vec::new();
+
+    for position in mesh.positions {
+        let vertex = transform.apply(position);
+        vertex_lines.push(format!("v {} {} {}", vertex.x, vertex.y, vertex.z));
+    }
+
+    let mut corner_index = 1;
+    for polygon in mesh.polygons {
+        let mut face_line = "f".to_string();
+        for i in transform.corner_order(polygon.indices.len()) {
+            let normal = transform.apply_normal(polygon.normal);
+            let uvs = polygon.uvs[i];
+            normal_lines.push(format!("vn {} {} {}", normal.x, normal.y, normal.z));
+            uv_lines.push(format!("vt {} {}", uvs.x, uvs.y));
+            face_line.push_str(&format!(
+                " {}/{}/{}",
+                polygon.indices[i] + 1,
+                corner_index,
+                corner_index
+            ));
+            corner_index += 1;
+        }
+        face_lines.push(face_line);
+    }
+
+    assemble_obj_file(
+        mtl_file_name,
+        vertex_lines,
+        normal_lines,
+        uv_lines,
+        face_lines,
+    )
+}
+
+/// Writes triangles with positions and normals only, without uvs or a
+/// material, as used for colliders.
+pub fn generate_collider_obj_file(transform: &Transform, triangles: Vec<Triangle>) -> String {
+    let mut vertex_lines = Vec::new();
+    let mut normal_lines = Vec::new();
+    let mut face_lines = Vec::new();
+
+    for triangle in triangles {
+        let normal = transform.apply_normal(triangle.normal);
+        normal_lines.push(format!("vn {} {} {}", normal.x, normal.y, normal.z));
+        let mut face_line = "f".to_string();
+        for i in transform.corner_order(3) {
+            let vertex = transform.apply(triangle.vertices[i]);
+            vertex_lines.push(format!("v {} {} {}", vertex.x, vertex.y, vertex.z));
+            face_line.push_str(&format!(" {}//{}", vertex_lines.len(), normal_lines.len()));
+        }
+        face_lines.push(face_line);
+    }
+
+    let mut obj_lines = vec!["o collider".to_string(), "# Vertices".to_string()];
+    obj_lines.append(&mut vertex_lines);
+    obj_lines.push("# Normals".to_string());
+    obj_lines.append(&mut normal_lines);
+    obj_lines.push("# Faces".to_string());
+    obj_lines.append(&mut face_lines);
+    obj_lines.join("\n")
+}
+
+fn assemble_obj_file(
+    mtl_file_name: &Path,
+    mut vertex_lines: Vec<String>,
+    mut normal_lines: Vec<String>,
+    mut uv_lines: Vec<String>,
+    mut face_lines: Vec<String>,
+) -> String {
     let mut obj_lines = Vec::new();
     obj_lines.push(format!(
         "mtllib {}",
@@ -58,7 +293,7 @@ pub fn generate_obj_file(
     obj_lines.join("\n")
 }
 
-pub fn generate_mtl_file(texture_file_name: &PathBuf) -> String {
+pub fn generate_mtl_file(texture_file_name: &Path) -> String {
     format!(
         r#"newmtl material
 Ka 0.2 0.2 0.2
@@ -71,3 +306,108 @@ map_Kd {}
     )
     .to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        geometry::{content_of, convert_to_geometry, cube, generate_quads},
+        tjunction::split_t_junctions,
+        triangulate::{Diagonal, triangulate_quads},
+        validate::weld_polygons,
+    };
+
+    /// Checks that every face of a box points away from its center, both by
+    /// its normal and by the winding of its corners.
+    fn assert_outward(obj: &str, axes: &str) {
+        let parse = |line: &str| -> Vec3 {
+            let values: Vec<f32> = line
+                .split(' ')
+                .skip(1)
+                .map(|v| v.parse().unwrap())
+                .collect();
+            Vec3::from_slice(&values)
+        };
+        let vertices: Vec<_> = obj
+            .lines()
+            .filter(|l| l.starts_with("v "))
+            .map(parse)
+            .collect();
+        let normals: Vec<_> = obj
+            .lines()
+            .filter(|l| l.starts_with("vn "))
+            .map(parse)
+            .collect();
+        let center = vertices.iter().sum::<Vec3>() / vertices.len() as f32;
+        for face in obj.lines().filter(|l| l.starts_with("f ")) {
+            let corners: Vec<(Vec3, Vec3)> = face
+                .split(' ')
+                .skip(1)
+                .map(|corner| {
+                    // Colliders leave out the uv index, as in `1//1`.
+                    let indices: Vec<&str> = corner.split('/').collect();
+                    let index = |i: &str| i.parse::<usize>().unwrap() - 1;
+                    (vertices[index(indices[0])], normals[index(indices[2])])
+                })
+                .collect();
+            let normal = corners[0].1;
+            let centroid = corners.iter().map(|c| c.0).sum::<Vec3>() / corners.len() as f32;
+            assert!(
+                normal.dot(centroid - center) > 0.0,
+                "Inward normal with {}: {}",
+                axes,
+                face
+            );
+            let winding = (corners[1].0 - corners[0].0).cross(corners[2].0 - corners[0].0);
+            assert!(
+                winding.dot(normal) > 0.0,
+                "Clockwise face with {}: {}",
+                axes,
+                face
+            );
+        }
+    }
+
+    #[test]
+    fn every_axes_preset_keeps_faces_outward() {
+        let content = content_of(cube(IVec3::ZERO, IVec3::new(2, 3, 4)));
+        let geometry = convert_to_geometry(&content).unwrap();
+        let mtl = Path::new("box.mtl");
+        for preset in ["+x+y+z", "-x+y+z", "+x-z+y", "-x+z+y", "+z+x+y", "-y-x-z"] {
+            let transform = Transform::new(&content, preset.parse().unwrap());
+            let quads = generate_quads(&geometry);
+            let triangles = triangulate_quads(&quads, &geometry, Diagonal::Fixed);
+            let collider = triangulate_quads(&quads, &geometry, Diagonal::Fixed);
+            let polygons = split_t_junctions(&quads);
+            let welded = weld_polygons(&polygons, true);
+            for obj in [
+                generate_obj_file(&transform, quads, mtl),
+                generate_triangle_obj_file(&transform, triangles, mtl),
+                generate_polygon_obj_file(&transform, polygons, mtl),
+                generate_welded_obj_file(&transform, welded, mtl),
+                generate_collider_obj_file(&transform, collider),
+            ] {
+                assert_outward(&obj, preset);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_axes() {
+        let axes: Axes = "+x-z+y".parse().unwrap();
+        assert_eq!(
+            axes.apply(Vec3::new(1.0, 2.0, 3.0)),
+            Vec3::new(1.0, -3.0, 2.0)
+        );
+        assert!(!axes.mirrors());
+        assert!("-x+y+z".parse::<Axes>().unwrap().mirrors());
+        assert_eq!("-X+Y+Z".parse::<Axes>().unwrap(), Axes::default());
+        for invalid in ["", "+x+y", "+x+x+z", "x+y+z", "+x+y+w", "+x+y+z+x"] {
+            assert!(
+                invalid.parse::<Axes>().is_err(),
+                "Expected error for {}",
+                invalid
+            );
+        }
+    }
+}
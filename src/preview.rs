@@ -0,0 +1,229 @@
+use bevy_math::{Vec2, Vec3};
+use image::{Rgba, RgbaImage};
+
+use crate::{geometry::Quad, obj::Transform};
+
+/// The directions the views look from, in reading order: isometric from the
+/// front right, isometric from the back left, from the front and from the
+/// top.
+const VIEWS: [Vec3; 4] = [
+    Vec3::new(1.0, 1.0, 1.0),
+    Vec3::new(-1.0, 1.0, -1.0),
+    Vec3::new(0.0, 0.0, 1.0),
+    Vec3::new(0.0, 1.0, 0.0),
+];
+
+const MARGIN: f32 = 0.05;
+
+/// Renders the textured quads from every view into a 2x2 sheet of square
+/// tiles, with a transparent background. The model is shown with X negated,
+/// as exported with the default axes.
+pub fn render_preview(
+    quads: &[Quad],
+    texture: &RgbaImage,
+    transform: &Transform,
+    tile_size: u32,
+) -> RgbaImage {
+    let mut image = RgbaImage::new(tile_size * 2, tile_size * 2);
+    for (i, view) in VIEWS.iter().enumerate() {
+        let tile = render_view(quads, texture, transform, *view, tile_size);
+        let (x, y) = (i as u32 % 2 * tile_size, i as u32 / 2 * tile_size);
+        image::imageops::replace(&mut image, &tile, x as i64, y as i64);
+    }
+    image
+}
+
+/// Renders the textured quads from the first isometric view only, as used
+/// for thumbnails.
+pub fn render_thumbnail(
+    quads: &[Quad],
+    texture: &RgbaImage,
+    transform: &Transform,
+    size: u32,
+) -> RgbaImage {
+    render_view(quads, texture, transform, VIEWS[0], size)
+}
+
+/// An orthographic camera looking along `forward`, mapping points to pixel
+/// coordinates and depth.
+struct Camera {
+    right: Vec3,
+    up: Vec3,
+    forward: Vec3,
+    center: Vec2,
+    scale: f32,
+    tile_size: f32,
+}
+
+impl Camera {
+    fn new(view: Vec3, points: &[Vec3], tile_size: u32) -> Self {
+        let forward = -view.normalize();
+        let world_up = if forward.cross(Vec3::Y).length() < 1e-6 {
+            Vec3::NEG_Z
+        } else {
+            Vec3::Y
+        };
+        let right = forward.cross(world_up).normalize();
+        let up = right.cross(forward);
+        let projected = points.iter().map(|p| Vec2::new(p.dot(right), p.dot(up)));
+        let min = projected.clone().fold(Vec2::MAX, Vec2::min);
+        let max = projected.fold(Vec2::MIN, Vec2::max);
+        let extent = (max - min).max_element().max(1e-6);
+        let tile_size = tile_size as f32;
+        Self {
+            right,
+            up,
+            forward,
+            center: (min + max) / 2.0,
+            scale: tile_size * (1.0 - 2.0 * MARGIN) / extent,
+            tile_size,
+        }
+    }
+
+    fn project(&self, p: Vec3) -> Vec3 {
+        let screen = (Vec2::new(p.dot(self.right), p.dot(self.up)) - self.center) * self.scale;
+        Vec3::new(
+            self.tile_size / 2.0 + screen.x,
+            self.tile_size / 2.0 - screen.y,
+            p.dot(self.forward),
+        )
+    }
+}
+
+fn render_view(
+    quads: &[Quad],
+    texture: &RgbaImage,
+    transform: &Transform,
+    view: Vec3,
+    tile_size: u32,
+) -> RgbaImage {
+    let mirror = Vec3::new(-1.0, 1.0, 1.0);
+    let place = |v| transform.model_position(v) * mirror;
+    let points: Vec<_> = quads.iter().flat_map(|q| q.vertices.map(place)).collect();
+    let mut image = RgbaImage::new(tile_size, tile_size);
+    if points.is_empty() {
+        return image;
+    }
+    let camera = Camera::new(view, &points, tile_size);
+    let mut depth = vec![f32::INFINITY; (tile_size * tile_size) as usize];
+    for quad in quads {
+        let normal = quad.normal * mirror;
+        if normal.dot(camera.forward) >= 0.0 {
+            continue;
+        }
+        let corners = quad.vertices.map(|v| camera.project(place(v)));
+        for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+            let triangle = [corners[a], corners[b], corners[c]];
+            let uvs = [quad.uvs[a], quad.uvs[b], quad.uvs[c]];
+            draw_triangle(&mut image, &mut depth, triangle, uvs, texture, normal);
+        }
+    }
+    image
+}
+
+fn draw_triangle(
+    image: &mut RgbaImage,
+    depth: &mut [f32],
+    [a, b, c]: [Vec3; 3],
+    uvs: [Vec2; 3],
+    texture: &RgbaImage,
+    normal: Vec3,
+) {
+    let area = edge(a, b, c);
+    if area.abs() < 1e-9 {
+        return;
+    }
+    let (width, height) = image.dimensions();
+    let min = a.min(b).min(c).floor().max(Vec3::ZERO);
+    let max = a.max(b).max(c).ceil();
+    let max_x = (max.x as u32).min(width);
+    let max_y = (max.y as u32).min(height);
+    for y in min.y as u32..max_y {
+        for x in min.x as u32..max_x {
+            let p = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, 0.0);
+            let weights = Vec3::new(edge(b, c, p), edge(c, a, p), edge(a, b, p)) / area;
+            if weights.min_element() < 0.0 {
+                continue;
+            }
+            let z = weights.dot(Vec3::new(a.z, b.z, c.z));
+            let index = (y * width + x) as usize;
+            if z >= depth[index] {
+                continue;
+            }
+            depth[index] = z;
+            let uv = uvs[0] * weights.x + uvs[1] * weights.y + uvs[2] * weights.z;
+            image.put_pixel(x, y, shade(sample(texture, uv), normal));
+        }
+    }
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p` on screen.
+fn edge(a: Vec3, b: Vec3, p: Vec3) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+/// Samples the nearest texel, with `v` pointing up as in OBJ files.
+fn sample(texture: &RgbaImage, uv: Vec2) -> Rgba<u8> {
+    let (width, height) = texture.dimensions();
+    let x = ((uv.x * width as f32) as u32).min(width - 1);
+    let y = (((1.0 - uv.y) * height as f32) as u32).min(height - 1);
+    *texture.get_pixel(x, y)
+}
+
+/// Darkens faces turned away from a light above the front right, so that
+/// neighbouring faces stay apart.
+fn shade(color: Rgba<u8>, normal: Vec3) -> Rgba<u8> {
+    let light = Vec3::new(-0.3, 1.0, 0.6).normalize();
+    let factor = 0.55 + 0.45 * normal.dot(light).max(0.0);
+    let [r, g, b, _] = color.0.map(|c| (c as f32 * factor).round() as u8);
+    Rgba([r, g, b, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        geometry::{GeometryObject, content_of, cube, textured_quads_of},
+        obj::Axes,
+        texture::create_texture_file,
+    };
+    use bevy_math::IVec3;
+
+    fn textured(obj: GeometryObject) -> (Vec<Quad>, RgbaImage, Transform) {
+        let transform = Transform::new(&content_of(obj.clone()), Axes::default());
+        let (quads, tex_quads, size) = textured_quads_of(obj);
+        (quads, create_texture_file(&tex_quads, size), transform)
+    }
+
+    #[test]
+    fn renders_every_view_on_transparent_background() {
+        let (quads, texture, transform) = textured(cube(IVec3::ZERO, IVec3::new(2, 1, 3)));
+        let image = render_preview(&quads, &texture, &transform, 64);
+        assert_eq!(image.dimensions(), (128, 128));
+        for (x, y) in [(0, 0), (64, 0), (0, 64), (64, 64)] {
+            assert_eq!(image.get_pixel(x, y).0[3], 0);
+            assert_eq!(image.get_pixel(x + 32, y + 32).0[3], 255);
+        }
+    }
+
+    #[test]
+    fn top_view_shows_nearest_top_face() {
+        // A small block on top of a wide slab covers the slab's top face in
+        // the middle of the view.
+        let (quads, texture, transform) = textured(GeometryObject::Union(
+            Box::new(cube(IVec3::ZERO, IVec3::new(5, 1, 5))),
+            Box::new(cube(IVec3::new(2, 1, 2), IVec3::ONE)),
+        ));
+        let tile = render_view(&quads, &texture, &transform, VIEWS[3], 50);
+        let top = |y: i32| {
+            let quad = quads
+                .iter()
+                .find(|q| q.normal == Vec3::Y && q.vertices[0].y == y)
+                .unwrap();
+            let center = quad.uvs.iter().sum::<Vec2>() / 4.0;
+            shade(sample(&texture, center), Vec3::Y)
+        };
+        assert_eq!(*tile.get_pixel(25, 25), top(2));
+        assert_eq!(*tile.get_pixel(5, 5), top(1));
+    }
+}
@@ -0,0 +1,153 @@
+use image::{Rgba, RgbaImage};
+
+const BACKGROUND: Rgba<u8> = Rgba([48, 48, 48, 255]);
+const TEXT: Rgba<u8> = Rgba([230, 230, 230, 255]);
+
+/// The scale of the 3x5 glyphs, and the space around the labels.
+const GLYPH_SCALE: u32 = 2;
+const PADDING: u32 = 4;
+
+/// Tiles the thumbnails into a roughly square grid on an opaque background,
+/// with each name printed below its thumbnail.
+pub fn render_contact_sheet(thumbnails: &[(String, RgbaImage)], size: u32) -> RgbaImage {
+    let columns = (thumbnails.len() as f64).sqrt().ceil().max(1.0) as u32;
+    let rows = (thumbnails.len() as u32).div_ceil(columns).max(1);
+    let label_height = 5 * GLYPH_SCALE + 2 * PADDING;
+    let cell_height = size + label_height;
+    let mut sheet = RgbaImage::from_pixel(columns * size, rows * cell_height, BACKGROUND);
+    for (i, (name, thumbnail)) in thumbnails.iter().enumerate() {
+        let (x, y) = (i as u32 % columns * size, i as u32 / columns * cell_height);
+        image::imageops::overlay(&mut sheet, thumbnail, x as i64, y as i64);
+        draw_label(&mut sheet, name, x, y + size + PADDING, size);
+    }
+    sheet
+}
+
+/// Draws the text centered in the given width, cutting it off with `..` if
+/// it does not fit.
+fn draw_label(image: &mut RgbaImage, text: &str, x: u32, y: u32, width: u32) {
+    let advance = 4 * GLYPH_SCALE;
+    let fits = ((width.saturating_sub(2 * PADDING) + GLYPH_SCALE) / advance) as usize;
+    let mut chars: Vec<char> = text.chars().collect();
+    if chars.len() > fits {
+        chars.truncate(fits.saturating_sub(2));
+        chars.extend("..".chars());
+    }
+    let text_width = (chars.len() as u32 * advance).saturating_sub(GLYPH_SCALE);
+    let mut x = x + width.saturating_sub(text_width) / 2;
+    for c in chars {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..GLYPH_SCALE {
+                    for dx in 0..GLYPH_SCALE {
+                        let px = x + column * GLYPH_SCALE + dx;
+                        let py = y + row as u32 * GLYPH_SCALE + dy;
+                        if px < image.width() && py < image.height() {
+                            image.put_pixel(px, py, TEXT);
+                        }
+                    }
+                }
+            }
+        }
+        x += advance;
+    }
+}
+
+/// The rows of a 3x5 glyph from top to bottom, with the leftmost pixel in the
+/// highest bit. Letters are shown in upper case.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ' ' => [0b000; 5],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_pixels(image: &RgbaImage, x: u32, y: u32, width: u32, height: u32) -> usize {
+        let mut count = 0;
+        for py in y..y + height {
+            for px in x..x + width {
+                count += (*image.get_pixel(px, py) == TEXT) as usize;
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn tiles_thumbnails_with_labels() {
+        let red = RgbaImage::from_pixel(32, 32, Rgba([255, 0, 0, 255]));
+        let clear = RgbaImage::new(32, 32);
+        let thumbnails = vec![
+            ("a".to_string(), red.clone()),
+            ("b".to_string(), clear),
+            ("long_model_name".to_string(), red),
+        ];
+        let sheet = render_contact_sheet(&thumbnails, 32);
+        let cell_height = 32 + 5 * GLYPH_SCALE + 2 * PADDING;
+        assert_eq!(sheet.dimensions(), (64, 2 * cell_height));
+
+        assert_eq!(*sheet.get_pixel(16, 16), Rgba([255, 0, 0, 255]));
+        assert_eq!(*sheet.get_pixel(48, 16), BACKGROUND);
+        assert_eq!(
+            *sheet.get_pixel(16, cell_height + 16),
+            Rgba([255, 0, 0, 255])
+        );
+        assert_eq!(*sheet.get_pixel(48, cell_height + 16), BACKGROUND);
+
+        // "a" and "b" both have 10 set pixels, scaled up by 2 in each axis.
+        assert_eq!(text_pixels(&sheet, 0, 32, 32, cell_height - 32), 10 * 4);
+        assert_eq!(text_pixels(&sheet, 32, 32, 32, cell_height - 32), 10 * 4);
+        // Long names stay inside their cell.
+        let label = text_pixels(&sheet, 0, cell_height + 32, 32, cell_height - 32);
+        assert!(label > 0);
+        assert_eq!(
+            text_pixels(&sheet, 0, 0, 64, 2 * cell_height),
+            20 * 4 + label
+        );
+    }
+}
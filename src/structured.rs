@@ -0,0 +1,270 @@
+use anyhow::{Result, anyhow, ensure};
+use bevy_math::{IVec3, Vec3};
+use serde::Deserialize;
+
+use crate::{
+    app::Cube,
+    geometry::{Bounds, Neighbourhood, NeighbourhoodKind, check_repeat_steps},
+    syntax::{Document, Expr, Header, Node, Operator, Syntax},
+};
+
+/// A model in JSON or RON, for models generated by other tools. It mirrors
+/// the header and the geometry tree of the text format.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelFile {
+    pub resolution: i32,
+    pub origin: [f32; 3],
+    #[serde(default = "default_scale")]
+    pub scale: [f32; 3],
+    #[serde(default = "default_unit")]
+    pub unit: f32,
+    pub geometry: Geometry,
+}
+
+fn default_scale() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn default_unit() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Geometry {
+    Cube {
+        corner: [i32; 3],
+        size: [i32; 3],
+    },
+    /// Another model file, relative to this one.
+    Include(String),
+    /// The intersection of all operands.
+    Intersection(Vec<Geometry>),
+    /// The union of all operands.
+    Union(Vec<Geometry>),
+    Minus(Box<Geometry>, Box<Geometry>),
+    SymmetricDifference(Box<Geometry>, Box<Geometry>),
+    Wireframe {
+        geometry: Box<Geometry>,
+        #[serde(default)]
+        neighbourhood: NeighbourhoodParams,
+    },
+    Hull {
+        geometry: Box<Geometry>,
+        #[serde(default)]
+        neighbourhood: NeighbourhoodParams,
+    },
+    Grow {
+        geometry: Box<Geometry>,
+        #[serde(default)]
+        neighbourhood: NeighbourhoodParams,
+    },
+    Shrink {
+        geometry: Box<Geometry>,
+        #[serde(default)]
+        neighbourhood: NeighbourhoodParams,
+    },
+    Open {
+        geometry: Box<Geometry>,
+        #[serde(default)]
+        neighbourhood: NeighbourhoodParams,
+    },
+    Close {
+        geometry: Box<Geometry>,
+        #[serde(default)]
+        neighbourhood: NeighbourhoodParams,
+    },
+    Shell {
+        geometry: Box<Geometry>,
+        #[serde(default)]
+        neighbourhood: NeighbourhoodParams,
+        /// The direction of the side to leave open, such as `[0, 1, 0]`.
+        #[serde(default)]
+        open: Option<[i32; 3]>,
+    },
+    Repeat {
+        geometry: Box<Geometry>,
+        steps: Vec<RepeatStep>,
+    },
+    /// Keeps the voxels from `min` (inclusive) to `max` (exclusive), where
+    /// missing coordinates are unbounded.
+    Crop {
+        geometry: Box<Geometry>,
+        #[serde(default)]
+        min: [Option<i32>; 3],
+        #[serde(default)]
+        max: [Option<i32>; 3],
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NeighbourhoodParams {
+    pub radius: i32,
+    pub kind: NeighbourhoodKind,
+    /// Mask of the axes the neighbourhood extends along, 1 for enabled axes.
+    pub axes: [i32; 3],
+}
+
+impl Default for NeighbourhoodParams {
+    fn default() -> Self {
+        let neighbourhood = Neighbourhood::default();
+        Self {
+            radius: neighbourhood.radius,
+            kind: neighbourhood.kind,
+            axes: neighbourhood.axes.to_array(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepeatStep {
+    pub step: [i32; 3],
+    pub count: i32,
+}
+
+impl ModelFile {
+    /// Converts the model into the syntax tree of the text format, checking
+    /// the same constraints as its parser.
+    pub fn into_document(self) -> Result<Document> {
+        ensure!(self.resolution > 0, "Invalid resolution");
+        ensure!(
+            self.scale.iter().all(|&v| v > 0.0 && v.is_finite()),
+            "Invalid scale"
+        );
+        ensure!(self.unit > 0.0 && self.unit.is_finite(), "Invalid unit");
+        Ok(Document {
+            header: Header {
+                comments: Vec::new(),
+                trailing: None,
+                resolution: self.resolution,
+                origin: Vec3::from_array(self.origin),
+                scale: Vec3::from_array(self.scale),
+                unit: self.unit,
+                syntax: Syntax::Prefix,
+            },
+            expr: self.geometry.into_expr()?,
+            comments: Vec::new(),
+        })
+    }
+}
+
+impl Geometry {
+    fn into_expr(self) -> Result<Expr> {
+        let node = match self {
+            Geometry::Cube { corner, size } => {
+                let size = IVec3::from_array(size);
+                ensure!(size.x > 0 && size.y > 0 && size.z > 0, "Invalid size");
+                Node::Cube(Cube {
+                    corner: IVec3::from_array(corner),
+                    size,
+                })
+            }
+            Geometry::Include(file) => Node::Include(file),
+            Geometry::Intersection(operands) => return fold('&', operands),
+            Geometry::Union(operands) => return fold('+', operands),
+            Geometry::Minus(left, right) => return binary('-', *left, *right),
+            Geometry::SymmetricDifference(left, right) => return binary('/', *left, *right),
+            Geometry::Wireframe {
+                geometry,
+                neighbourhood,
+            } => unary(Operator::Wireframe(neighbourhood.try_into()?), *geometry)?,
+            Geometry::Hull {
+                geometry,
+                neighbourhood,
+            } => unary(Operator::Hull(neighbourhood.try_into()?), *geometry)?,
+            Geometry::Grow {
+                geometry,
+                neighbourhood,
+            } => unary(Operator::Grow(neighbourhood.try_into()?), *geometry)?,
+            Geometry::Shrink {
+                geometry,
+                neighbourhood,
+            } => unary(Operator::Shrink(neighbourhood.try_into()?), *geometry)?,
+            Geometry::Open {
+                geometry,
+                neighbourhood,
+            } => unary(Operator::Open(neighbourhood.try_into()?), *geometry)?,
+            Geometry::Close {
+                geometry,
+                neighbourhood,
+            } => unary(Operator::Close(neighbourhood.try_into()?), *geometry)?,
+            Geometry::Shell {
+                geometry,
+                neighbourhood,
+                open,
+            } => {
+                let open = open.map(IVec3::from_array);
+                if let Some(dir) = open {
+                    ensure!(dir.abs().element_sum() == 1, "Invalid direction: {}", dir);
+                }
+                unary(Operator::Shell(neighbourhood.try_into()?, open), *geometry)?
+            }
+            Geometry::Repeat { geometry, steps } => {
+                ensure!(!steps.is_empty(), "Expected at least one step");
+                let steps = steps
+                    .into_iter()
+                    .map(|step| (IVec3::from_array(step.step), step.count))
+                    .collect::<Vec<_>>();
+                check_repeat_steps(&steps)?;
+                unary(Operator::Repeat(steps), *geometry)?
+            }
+            Geometry::Crop { geometry, min, max } => {
+                let bounds = Bounds {
+                    min: IVec3::from_array(min.map(|v| v.unwrap_or(i32::MIN))),
+                    max: IVec3::from_array(max.map(|v| v.unwrap_or(i32::MAX))),
+                };
+                unary(Operator::Crop(bounds), *geometry)?
+            }
+        };
+        Ok(Expr {
+            comments: Vec::new(),
+            trailing: None,
+            node,
+        })
+    }
+}
+
+impl TryFrom<NeighbourhoodParams> for Neighbourhood {
+    type Error = anyhow::Error;
+
+    fn try_from(params: NeighbourhoodParams) -> Result<Self> {
+        let axes = IVec3::from_array(params.axes).signum().abs();
+        ensure!(params.radius > 0, "Invalid radius");
+        ensure!(
+            params.radius <= Neighbourhood::MAX_RADIUS,
+            "Radius {} is too large, the largest is {}",
+            params.radius,
+            Neighbourhood::MAX_RADIUS
+        );
+        ensure!(axes != IVec3::ZERO, "No axes given");
+        Ok(Neighbourhood {
+            radius: params.radius,
+            kind: params.kind,
+            axes,
+        })
+    }
+}
+
+fn unary(operator: Operator, operand: Geometry) -> Result<Node> {
+    Ok(Node::Unary(operator, Box::new(operand.into_expr()?)))
+}
+
+fn binary(operator: char, left: Geometry, right: Geometry) -> Result<Expr> {
+    Ok(Expr::binary(
+        operator,
+        left.into_expr()?,
+        right.into_expr()?,
+    ))
+}
+
+/// Combines the operands from left to right.
+fn fold(operator: char, operands: Vec<Geometry>) -> Result<Expr> {
+    let mut operands = operands.into_iter();
+    let first = operands.next().ok_or(anyhow!("Expected an operand"))?;
+    operands.try_fold(first.into_expr()?, |left, right| {
+        Ok(Expr::binary(operator, left, right.into_expr()?))
+    })
+}
@@ -0,0 +1,418 @@
+use bevy_math::{IVec3, Vec3};
+
+use crate::{
+    app::Cube,
+    geometry::{Bounds, Neighbourhood, NeighbourhoodKind},
+};
+
+/// The expression syntax of a file, chosen by the version marker in its
+/// header (`16; 8 8 8; v2` for infix).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Operators precede their operands, as in `+ A B`.
+    #[default]
+    Prefix,
+    /// Binary operators go between their operands, as in `A + B & C`, with
+    /// parentheses for grouping.
+    Infix,
+}
+
+/// A parsed model file, keeping its comments so that it can be printed back.
+#[derive(Debug)]
+pub struct Document {
+    pub header: Header,
+    pub expr: Expr,
+    /// Comments after the expression.
+    pub comments: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Header {
+    /// Comments on the lines before the header.
+    pub comments: Vec<String>,
+    pub trailing: Option<String>,
+    pub resolution: i32,
+    pub origin: Vec3,
+    /// The size of a voxel along every axis, relative to the resolution.
+    pub scale: Vec3,
+    /// The length of one unit of the model in the exported mesh.
+    pub unit: f32,
+    pub syntax: Syntax,
+}
+
+#[derive(Debug)]
+pub struct Expr {
+    /// Comments on the lines before the expression.
+    pub comments: Vec<String>,
+    /// A comment at the end of the expression's first line.
+    pub trailing: Option<String>,
+    pub node: Node,
+}
+
+#[derive(Debug)]
+pub enum Node {
+    Cube(Cube),
+    Include(String),
+    Unary(Operator, Box<Expr>),
+    /// A binary operator, one of `&`, `+`, `-` and `/`.
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+pub enum Operator {
+    Wireframe(Neighbourhood),
+    Hull(Neighbourhood),
+    Grow(Neighbourhood),
+    Shrink(Neighbourhood),
+    Open(Neighbourhood),
+    Close(Neighbourhood),
+    Shell(Neighbourhood, Option<IVec3>),
+    Repeat(Vec<(IVec3, i32)>),
+    Crop(Bounds),
+    Cut(HalfSpace),
+}
+
+/// A crop bounded on a single side, keeping the side it was written with so
+/// that it prints back unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HalfSpace {
+    pub axis: usize,
+    /// Whether the voxels at or above `value` are kept, rather than the ones
+    /// below it.
+    pub above: bool,
+    pub value: i32,
+}
+
+impl HalfSpace {
+    pub fn bounds(&self) -> Bounds {
+        let mut bounds = Bounds::ALL;
+        if self.above {
+            bounds.min[self.axis] = self.value;
+        } else {
+            bounds.max[self.axis] = self.value;
+        }
+        bounds
+    }
+}
+
+impl Expr {
+    pub fn binary(operator: char, left: Expr, right: Expr) -> Self {
+        Self {
+            comments: Vec::new(),
+            trailing: None,
+            node: Node::Binary(operator, Box::new(left), Box::new(right)),
+        }
+    }
+}
+
+/// The printed lines of an expression, with its own leading comments kept
+/// apart so that operators can be put in front of its first line.
+struct Block {
+    comments: Vec<String>,
+    lines: Vec<String>,
+}
+
+impl Block {
+    fn into_lines(self) -> Vec<String> {
+        [self.comments, self.lines].concat()
+    }
+}
+
+const INDENT: usize = 2;
+
+/// Prints a document in canonical form: binary operators on their own line
+/// with their operands indented below them, one cube per line, and normalized
+/// spacing and parameters.
+pub fn format_document(document: &Document) -> String {
+    let header = &document.header;
+    let mut lines = header.comments.clone();
+    let origin = header.origin;
+    let mut header_line = format!(
+        "{}; {} {} {}",
+        header.resolution, origin.x, origin.y, origin.z
+    );
+    if header.scale != Vec3::ONE {
+        let scale = header.scale;
+        header_line.push_str(&format!("; scale {} {} {}", scale.x, scale.y, scale.z));
+    }
+    if header.unit != 1.0 {
+        header_line.push_str(&format!("; unit {}", header.unit));
+    }
+    if header.syntax == Syntax::Infix {
+        header_line.push_str("; v2");
+    }
+    lines.push(with_trailing(header_line, &header.trailing));
+    lines.push(String::new());
+    let block = match header.syntax {
+        Syntax::Prefix => format_prefix(&document.expr),
+        Syntax::Infix => format_infix_operand(&document.expr, false),
+    };
+    lines.extend(block.into_lines());
+    lines.extend(document.comments.iter().cloned());
+    lines.join("\n") + "\n"
+}
+
+fn format_prefix(expr: &Expr) -> Block {
+    let lines = match &expr.node {
+        Node::Binary(operator, left, right) => {
+            let mut lines = vec![with_trailing(operator.to_string(), &expr.trailing)];
+            for operand in [left, right] {
+                lines.extend(indent(format_prefix(operand).into_lines(), INDENT));
+            }
+            lines
+        }
+        Node::Unary(operator, operand) => {
+            join_unary(expr, format_operator(operator), format_prefix(operand))
+        }
+        _ => vec![with_trailing(format_leaf(&expr.node), &expr.trailing)],
+    };
+    Block {
+        comments: expr.comments.clone(),
+        lines,
+    }
+}
+
+fn format_infix(expr: &Expr) -> Block {
+    match &expr.node {
+        Node::Binary(..) => {
+            // Chains of operators with the same precedence are printed with
+            // one operand per line, as in `A + B - C`.
+            let level = precedence(expr);
+            let mut rights = Vec::new();
+            let mut current = expr;
+            while let Node::Binary(operator, left, right) = &current.node {
+                let commented = current.trailing.is_some() && !std::ptr::eq(current, expr);
+                if precedence(current) != level || commented {
+                    break;
+                }
+                rights.push((*operator, right));
+                current = left;
+            }
+            let mut block = format_infix_operand(current, precedence(current) < level);
+            for (operator, right) in rights.into_iter().rev() {
+                let operand = format_infix_operand(right, precedence(right) <= level);
+                block.lines.extend(indent(operand.comments, INDENT));
+                let mut lines = operand.lines.into_iter();
+                let first = lines.next().unwrap_or_default();
+                block
+                    .lines
+                    .push(format!("{}{} {}", " ".repeat(INDENT), operator, first));
+                block.lines.extend(indent(lines.collect(), 2 * INDENT));
+            }
+            block
+        }
+        Node::Unary(operator, operand) => {
+            let lines = join_unary(
+                expr,
+                format_operator(operator),
+                format_infix_operand(operand, matches!(operand.node, Node::Binary(..))),
+            );
+            Block {
+                comments: expr.comments.clone(),
+                lines,
+            }
+        }
+        _ => Block {
+            comments: expr.comments.clone(),
+            lines: vec![with_trailing(format_leaf(&expr.node), &expr.trailing)],
+        },
+    }
+}
+
+/// Prints an operand, in parentheses if needed. A binary expression with a
+/// trailing comment is always put in parentheses, as the comment follows the
+/// closing one.
+fn format_infix_operand(expr: &Expr, parenthesize: bool) -> Block {
+    let block = format_infix(expr);
+    let commented = matches!(expr.node, Node::Binary(..)) && expr.trailing.is_some();
+    if !parenthesize && !commented {
+        return block;
+    }
+    let mut lines = vec!["(".to_string()];
+    lines.extend(indent(block.into_lines(), INDENT));
+    lines.push(with_trailing(")".to_string(), &expr.trailing));
+    Block {
+        comments: Vec::new(),
+        lines,
+    }
+}
+
+/// Returns the binding strength of an expression's operator, where anything
+/// but a binary operator binds tightest.
+fn precedence(expr: &Expr) -> u8 {
+    match expr.node {
+        Node::Binary('&', ..) => 2,
+        Node::Binary(..) => 1,
+        _ => 3,
+    }
+}
+
+/// Puts a unary operator in front of its operand's first line, unless
+/// comments come between them.
+fn join_unary(expr: &Expr, operator: String, operand: Block) -> Vec<String> {
+    if expr.trailing.is_none() && operand.comments.is_empty() {
+        let mut lines = operand.lines;
+        lines[0] = format!("{} {}", operator, lines[0]);
+        lines
+    } else {
+        let mut lines = vec![with_trailing(operator, &expr.trailing)];
+        lines.extend(indent(operand.into_lines(), INDENT));
+        lines
+    }
+}
+
+fn format_leaf(node: &Node) -> String {
+    match node {
+        Node::Cube(cube) => format_cube(cube.corner, cube.size),
+        Node::Include(file) => format!("include \"{}\"", file),
+        _ => unreachable!("Not a leaf: {:?}", node),
+    }
+}
+
+fn format_cube(corner: IVec3, size: IVec3) -> String {
+    format!(
+        "({} {} {}; {} {} {})",
+        corner.x, corner.y, corner.z, size.x, size.y, size.z
+    )
+}
+
+fn format_operator(operator: &Operator) -> String {
+    match operator {
+        Operator::Wireframe(neighbourhood) => format_params('w', neighbourhood, None),
+        Operator::Hull(neighbourhood) => format_params('h', neighbourhood, None),
+        Operator::Grow(neighbourhood) => format_params('g', neighbourhood, None),
+        Operator::Shrink(neighbourhood) => format_params('s', neighbourhood, None),
+        Operator::Open(neighbourhood) => format_params('o', neighbourhood, None),
+        Operator::Close(neighbourhood) => format_params('c', neighbourhood, None),
+        Operator::Shell(neighbourhood, open) => {
+            let open = open.map(|dir| {
+                let sign = if dir.element_sum() < 0 { '-' } else { '+' };
+                let axis =
+                    ['x', 'y', 'z'][dir.abs().to_array().iter().position(|&d| d != 0).unwrap()];
+                format!("open={}{}", sign, axis)
+            });
+            format_params('e', neighbourhood, open)
+        }
+        Operator::Repeat(steps) => {
+            let steps = steps
+                .iter()
+                .map(|(step, count)| format!("{} {} {}; {}", step.x, step.y, step.z, count))
+                .collect::<Vec<_>>();
+            format!("r({})", steps.join("; "))
+        }
+        Operator::Crop(bounds) => format!("b{}", format_cube(bounds.min, bounds.max - bounds.min)),
+        Operator::Cut(half_space) => {
+            let axis = ['x', 'y', 'z'][half_space.axis];
+            let comparison = if half_space.above { ">=" } else { "<" };
+            format!("t({} {} {})", axis, comparison, half_space.value)
+        }
+    }
+}
+
+/// Prints the parameters that differ from the default, using the short form
+/// (`g3`) when only the radius does.
+fn format_params(operator: char, neighbourhood: &Neighbourhood, extra: Option<String>) -> String {
+    let default = Neighbourhood::default();
+    let mut params = Vec::new();
+    if neighbourhood.radius != default.radius {
+        params.push(format!("r={}", neighbourhood.radius));
+    }
+    if neighbourhood.kind != default.kind {
+        let kind = match neighbourhood.kind {
+            NeighbourhoodKind::Faces => "6",
+            NeighbourhoodKind::Edges => "18",
+            NeighbourhoodKind::Corners => "26",
+            NeighbourhoodKind::Sphere => "s",
+        };
+        params.push(format!("n={}", kind));
+    }
+    if neighbourhood.axes != default.axes {
+        let axes = ['x', 'y', 'z']
+            .into_iter()
+            .zip(neighbourhood.axes.to_array())
+            .filter(|(_, enabled)| *enabled != 0)
+            .map(|(axis, _)| axis)
+            .collect::<String>();
+        params.push(format!("axes={}", axes));
+    }
+    params.extend(extra);
+    match params.as_slice() {
+        [] => operator.to_string(),
+        [radius] if neighbourhood.radius != default.radius => {
+            format!("{}{}", operator, &radius[2..])
+        }
+        _ => format!("{}({})", operator, params.join(", ")),
+    }
+}
+
+fn with_trailing(line: String, trailing: &Option<String>) -> String {
+    match trailing {
+        Some(comment) => format!("{} {}", line, comment),
+        None => line,
+    }
+}
+
+fn indent(lines: Vec<String>, width: usize) -> Vec<String> {
+    lines
+        .into_iter()
+        .map(|line| format!("{}{}", " ".repeat(width), line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::parse_document;
+
+    fn format(content: &str) -> String {
+        format_document(&parse_document(content).unwrap())
+    }
+
+    #[test]
+    fn formats_prefix_canonically() {
+        let content = "# Header\n16;8 8 8;\n\n+ + # base\n(0 0  0;1 1 1)w g(r=2)(1 0 0; 1 1 1) // wire\n  -(0 0 0; 2 2 2)\n\n# inner\n   e(n=6, open=-y)(1 1 1; 1 1 1)\n# end\n";
+        let expected = "# Header\n16; 8 8 8\n\n+\n  + # base\n    (0 0 0; 1 1 1)\n    w g2 (1 0 0; 1 1 1) // wire\n  -\n    (0 0 0; 2 2 2)\n    # inner\n    e(n=6, open=-y) (1 1 1; 1 1 1)\n# end\n";
+        assert_eq!(format(content), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn formats_header_parts() {
+        let content = "16;8 8 8;v2 ; unit 100;scale 1  2 0.5\n(0 0 0; 1 1 1)";
+        let expected = "16; 8 8 8; scale 1 2 0.5; unit 100; v2\n\n(0 0 0; 1 1 1)\n";
+        assert_eq!(format(content), expected);
+        assert_eq!(
+            format("16; 8 8 8; scale 1 1 1; unit 1\n(0 0 0; 1 1 1)"),
+            "16; 8 8 8\n\n(0 0 0; 1 1 1)\n"
+        );
+    }
+
+    #[test]
+    fn formats_operator_parameters() {
+        let content = "1; 0 0 0\n/ / t(x <= 3) b(0 0 0; 2 2 2) (0 0 0; 1 1 1) r(1 0 0;2;0 1 0;3)(0 0 0; 1 1 1) g(r=1, n=26, axes=xz) (0 0 0; 1 1 1)";
+        let expected = "1; 0 0 0\n\n/\n  /\n    t(x < 4) b(0 0 0; 2 2 2) (0 0 0; 1 1 1)\n    r(1 0 0; 2; 0 1 0; 3) (0 0 0; 1 1 1)\n  g(axes=xz) (0 0 0; 1 1 1)\n";
+        assert_eq!(format(content), expected);
+    }
+
+    #[test]
+    fn formats_cuts_on_the_side_given() {
+        for (content, cut) in [
+            ("t(x >= -2147483648)", "t(x >= -2147483648)"),
+            ("t(y < 2147483647)", "t(y < 2147483647)"),
+            ("t(z > 2147483646)", "t(z >= 2147483647)"),
+        ] {
+            let content = format!("1; 0 0 0\n{} (0 0 0; 1 1 1)", content);
+            let expected = format!("1; 0 0 0\n\n{} (0 0 0; 1 1 1)\n", cut);
+            assert_eq!(format(&content), expected);
+            assert_eq!(format(&expected), expected);
+        }
+    }
+
+    #[test]
+    fn formats_infix_with_minimal_parentheses() {
+        let content = "16; 8 8 8; v2\n((0 0 0; 4 4 4) + (1 0 0; 1 1 1)) - ((2 0 0; 1 1 1) + (3 0 0; 1 1 1) & (0 0 0; 9 9 9)) # cut\n+ s ((0 0 0; 1 1 1) / (0 0 0; 2 2 2))";
+        let expected = "16; 8 8 8; v2\n\n(0 0 0; 4 4 4)\n  + (1 0 0; 1 1 1)\n  - (\n      (2 0 0; 1 1 1)\n        + (3 0 0; 1 1 1)\n            & (0 0 0; 9 9 9)\n    ) # cut\n  + s (\n      (0 0 0; 1 1 1)\n        / (0 0 0; 2 2 2)\n    )\n";
+        assert_eq!(format(content), expected);
+        assert_eq!(format(expected), expected);
+    }
+}
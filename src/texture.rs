@@ -1,8 +1,8 @@
 use crate::geometry::Quad;
 use bevy_math::Vec2;
-use image::{ImageBuffer, RgbaImage};
+use image::{ImageBuffer, Rgba, RgbaImage};
 
-pub fn sort_quads(quads: &mut Vec<Quad>) {
+pub fn sort_quads(quads: &mut [Quad]) {
     quads.sort_by(|a, b| {
         if a.tex_size.1 != b.tex_size.1 {
             b.tex_size.1.cmp(&a.tex_size.1)
@@ -22,7 +22,7 @@ pub fn pack_quad_texture(quads: &Vec<Quad>) -> (Vec<TexQuad>, usize) {
     (tex_quads.unwrap(), size)
 }
 
-pub fn apply_uv_to_quads(quads: &mut Vec<Quad>, tex_quads: &Vec<TexQuad>, size: usize) {
+pub fn apply_uv_to_quads(quads: &mut [Quad], tex_quads: &[TexQuad], size: usize) {
     for (quad, tex_quad) in quads.iter_mut().zip(tex_quads.iter()) {
         let min_uv = (
             tex_quad.x as f32 / size as f32,
@@ -39,22 +39,33 @@ pub fn apply_uv_to_quads(quads: &mut Vec<Quad>, tex_quads: &Vec<TexQuad>, size:
     }
 }
 
-pub fn create_texture_file(tex_quads: &Vec<TexQuad>, size: usize) -> RgbaImage {
+pub fn create_texture_file(tex_quads: &[TexQuad], size: usize) -> RgbaImage {
+    let colors: Vec<_> = tex_quads
+        .iter()
+        .enumerate()
+        .map(|(i, tex_quad)| {
+            Rgba([
+                (tex_quad.x * 255 / size) as u8,
+                (tex_quad.y * 255 / size) as u8,
+                255 - (i * 255 / tex_quads.len()) as u8,
+                255,
+            ])
+        })
+        .collect();
+    create_colored_texture_file(tex_quads, size, &colors)
+}
+
+/// Creates a texture filling the area of every quad with its given color.
+pub fn create_colored_texture_file(
+    tex_quads: &[TexQuad],
+    size: usize,
+    colors: &[Rgba<u8>],
+) -> RgbaImage {
     let mut img_buf = ImageBuffer::new(size as u32, size as u32);
-    for (i, tex_quad) in tex_quads.iter().enumerate() {
-        let color = [
-            (tex_quad.x * 255 / size) as u8,
-            (tex_quad.y * 255 / size) as u8,
-            255 - (i * 255 / tex_quads.len()) as u8,
-            255,
-        ];
+    for (tex_quad, color) in tex_quads.iter().zip(colors) {
         for y in 0..tex_quad.height {
             for x in 0..tex_quad.width {
-                img_buf.put_pixel(
-                    (tex_quad.x + x) as u32,
-                    (tex_quad.y + y) as u32,
-                    image::Rgba(color),
-                );
+                img_buf.put_pixel((tex_quad.x + x) as u32, (tex_quad.y + y) as u32, *color);
             }
         }
     }
@@ -96,7 +107,7 @@ fn try_pack_quad_texture(quads: &Vec<Quad>, size: usize) -> Option<Vec<TexQuad>>
     Some(tex_quads)
 }
 
-fn can_place_quad(texture: &Vec<u8>, size: usize, x: usize, y: usize, quad: &Quad) -> bool {
+fn can_place_quad(texture: &[u8], size: usize, x: usize, y: usize, quad: &Quad) -> bool {
     if x + quad.tex_size.0 > size || y + quad.tex_size.1 > size {
         return false;
     }
@@ -110,7 +121,7 @@ fn can_place_quad(texture: &Vec<u8>, size: usize, x: usize, y: usize, quad: &Qua
     true
 }
 
-fn place_quad(texture: &mut Vec<u8>, size: usize, x: usize, y: usize, quad: &Quad) {
+fn place_quad(texture: &mut [u8], size: usize, x: usize, y: usize, quad: &Quad) {
     for j in 0..quad.tex_size.1 {
         for i in 0..quad.tex_size.0 {
             texture[(y + j) * size + x + i] = 1;
@@ -125,3 +136,72 @@ pub struct TexQuad {
     width: usize,
     height: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_math::IVec3;
+
+    fn quads_with_sizes(sizes: &[(usize, usize)]) -> Vec<Quad> {
+        sizes
+            .iter()
+            .map(|&size| Quad::new([IVec3::ZERO, IVec3::Y, IVec3::Y + IVec3::Z, IVec3::Z], size))
+            .collect()
+    }
+
+    fn assert_packed(quads: &[Quad], tex_quads: &[TexQuad], size: usize) {
+        assert_eq!(quads.len(), tex_quads.len());
+        for (quad, tex_quad) in quads.iter().zip(tex_quads) {
+            assert_eq!((tex_quad.width, tex_quad.height), quad.tex_size);
+            assert!(tex_quad.x + tex_quad.width <= size);
+            assert!(tex_quad.y + tex_quad.height <= size);
+        }
+        for (i, a) in tex_quads.iter().enumerate() {
+            for b in &tex_quads[i + 1..] {
+                let overlaps = a.x < b.x + b.width
+                    && b.x < a.x + a.width
+                    && a.y < b.y + b.height
+                    && b.y < a.y + a.height;
+                assert!(!overlaps, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn packs_without_overlaps() {
+        let mut quads = quads_with_sizes(&[(3, 1), (1, 1), (2, 2), (5, 3), (1, 4), (2, 1), (1, 1)]);
+        sort_quads(&mut quads);
+        let (tex_quads, size) = pack_quad_texture(&quads);
+        assert_packed(&quads, &tex_quads, size);
+        assert!(size.is_power_of_two());
+    }
+
+    #[test]
+    fn uses_smallest_power_of_two() {
+        let quads = quads_with_sizes(&[(2, 2), (2, 2), (2, 2), (2, 2)]);
+        let (tex_quads, size) = pack_quad_texture(&quads);
+        assert_packed(&quads, &tex_quads, size);
+        assert_eq!(size, 4);
+    }
+
+    #[test]
+    fn uvs_cover_packed_rects() {
+        let mut quads = quads_with_sizes(&[(2, 1), (1, 1)]);
+        let (tex_quads, size) = pack_quad_texture(&quads);
+        apply_uv_to_quads(&mut quads, &tex_quads, size);
+        assert_eq!(size, 2);
+        assert_eq!(quads[0].uvs[0], Vec2::new(0.0, 0.5));
+        assert_eq!(quads[0].uvs[2], Vec2::new(1.0, 1.0));
+        assert_eq!(quads[1].uvs[0], Vec2::new(0.0, 0.0));
+        assert_eq!(quads[1].uvs[2], Vec2::new(0.5, 0.5));
+    }
+
+    #[test]
+    fn texture_has_packed_size() {
+        let quads = quads_with_sizes(&[(3, 2), (1, 1)]);
+        let (tex_quads, size) = pack_quad_texture(&quads);
+        let texture = create_texture_file(&tex_quads, size);
+        assert_eq!(texture.dimensions(), (size as u32, size as u32));
+        assert_eq!(texture.get_pixel(0, 0).0[3], 255);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+
+use bevy_math::{IVec3, Vec2, Vec3};
+
+use crate::geometry::Quad;
+
+#[derive(Debug)]
+pub struct Polygon {
+    pub vertices: Vec<IVec3>,
+    pub normal: Vec3,
+    pub uvs: Vec<Vec2>,
+}
+
+impl From<&Quad> for Polygon {
+    fn from(quad: &Quad) -> Self {
+        Self {
+            vertices: quad.vertices.to_vec(),
+            normal: quad.normal,
+            uvs: quad.uvs.to_vec(),
+        }
+    }
+}
+
+/// Inserts every mesh vertex that lies on a quad edge into that edge, so that
+/// neighbouring faces share all their vertices and the mesh has no cracks.
+pub fn split_t_junctions(quads: &[Quad]) -> Vec<Polygon> {
+    let vertices: HashSet<_> = quads.iter().flat_map(|q| q.vertices).collect();
+    let mut polygons = Vec::with_capacity(quads.len());
+    for quad in quads {
+        let mut polygon = Polygon {
+            vertices: Vec::new(),
+            normal: quad.normal,
+            uvs: Vec::new(),
+        };
+        for i in 0..4 {
+            let (a, b) = (quad.vertices[i], quad.vertices[(i + 1) % 4]);
+            let (uv_a, uv_b) = (quad.uvs[i], quad.uvs[(i + 1) % 4]);
+            polygon.vertices.push(a);
+            polygon.uvs.push(uv_a);
+            let length = (b - a).abs().max_element();
+            for (step, point) in edge_interior(a, b).enumerate() {
+                if vertices.contains(&point) {
+                    let t = (step + 1) as f32 / length as f32;
+                    polygon.vertices.push(point);
+                    polygon.uvs.push(uv_a.lerp(uv_b, t));
+                }
+            }
+        }
+        polygons.push(polygon);
+    }
+    polygons
+}
+
+/// Counts the vertices lying strictly inside an edge of another polygon.
+pub fn count_t_junctions(polygons: &[Polygon]) -> usize {
+    let vertices: HashSet<_> = polygons
+        .iter()
+        .flat_map(|p| p.vertices.iter().copied())
+        .collect();
+    let mut count = 0;
+    for polygon in polygons {
+        let n = polygon.vertices.len();
+        for i in 0..n {
+            let (a, b) = (polygon.vertices[i], polygon.vertices[(i + 1) % n]);
+            count += edge_interior(a, b).filter(|p| vertices.contains(p)).count();
+        }
+    }
+    count
+}
+
+/// Iterates over the lattice points strictly between the ends of an
+/// axis-aligned edge, starting next to `a`.
+fn edge_interior(a: IVec3, b: IVec3) -> impl Iterator<Item = IVec3> {
+    let dir = (b - a).signum();
+    let length = (b - a).abs().max_element();
+    (1..length).map(move |i| a + dir * i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{GeometryObject, cube, quads_of};
+
+    fn step() -> GeometryObject {
+        GeometryObject::Union(
+            Box::new(cube(IVec3::ZERO, IVec3::new(4, 1, 2))),
+            Box::new(cube(IVec3::ZERO, IVec3::new(1, 2, 1))),
+        )
+    }
+
+    #[test]
+    fn single_cube_has_no_t_junctions() {
+        let quads = quads_of(cube(IVec3::ZERO, IVec3::new(3, 2, 1)));
+        let polygons: Vec<_> = quads.iter().map(Polygon::from).collect();
+        assert_eq!(count_t_junctions(&polygons), 0);
+        assert!(
+            split_t_junctions(&quads)
+                .iter()
+                .all(|p| p.vertices.len() == 4)
+        );
+    }
+
+    #[test]
+    fn splitting_removes_all_t_junctions() {
+        let quads = quads_of(step());
+        let polygons: Vec<_> = quads.iter().map(Polygon::from).collect();
+        assert!(count_t_junctions(&polygons) > 0);
+
+        let split = split_t_junctions(&quads);
+        assert_eq!(split.len(), quads.len());
+        assert_eq!(count_t_junctions(&split), 0);
+    }
+
+    #[test]
+    fn inserted_vertices_interpolate_uvs() {
+        let quads = quads_of(step());
+        for (quad, polygon) in quads.iter().zip(split_t_junctions(&quads)) {
+            assert_eq!(polygon.vertices.len(), polygon.uvs.len());
+            let min = quad.vertices.iter().fold(IVec3::MAX, |acc, &v| acc.min(v));
+            let max = quad.vertices.iter().fold(IVec3::MIN, |acc, &v| acc.max(v));
+            for (vertex, uv) in polygon.vertices.iter().zip(&polygon.uvs) {
+                assert!(vertex.cmpge(min).all() && vertex.cmple(max).all());
+                assert!(uv.cmpge(Vec2::ZERO).all() && uv.cmple(Vec2::ONE).all());
+            }
+        }
+    }
+}
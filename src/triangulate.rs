@@ -0,0 +1,249 @@
+use bevy_math::{IVec3, Vec2, Vec3};
+use clap::ValueEnum;
+
+use crate::{
+    geometry::{Geometry, Quad},
+    tjunction::Polygon,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Diagonal {
+    /// Always split along the diagonal from vertex 0 to vertex 2.
+    Fixed,
+    /// Split along the diagonal whose corners have the larger ambient occlusion
+    /// sum, so that darkened corners stay confined to a single triangle.
+    Ao,
+}
+
+#[derive(Debug)]
+pub struct Triangle {
+    pub vertices: [IVec3; 3],
+    pub normal: Vec3,
+    pub uvs: [Vec2; 3],
+}
+
+/// Splits every quad into two triangles, keeping the counter-clockwise winding
+/// of the quad (seen from the side its normal points to).
+pub fn triangulate_quads(quads: &[Quad], geometry: &Geometry, diagonal: Diagonal) -> Vec<Triangle> {
+    let mut triangles = Vec::with_capacity(quads.len() * 2);
+    for quad in quads {
+        let flip = match diagonal {
+            Diagonal::Fixed => false,
+            Diagonal::Ao => {
+                let ao = get_quad_ao(quad, geometry);
+                ao[1] + ao[3] > ao[0] + ao[2]
+            }
+        };
+        let indices = if flip {
+            [[0, 1, 3], [1, 2, 3]]
+        } else {
+            [[0, 1, 2], [0, 2, 3]]
+        };
+        for [a, b, c] in indices {
+            triangles.push(Triangle {
+                vertices: [quad.vertices[a], quad.vertices[b], quad.vertices[c]],
+                normal: quad.normal,
+                uvs: [quad.uvs[a], quad.uvs[b], quad.uvs[c]],
+            });
+        }
+    }
+    triangles
+}
+
+/// Triangulates convex polygons by clipping ears at their corners, skipping
+/// vertices that lie on a straight edge so that no triangle is degenerate.
+pub fn triangulate_polygons(polygons: &[Polygon]) -> Vec<Triangle> {
+    let mut triangles = Vec::new();
+    for polygon in polygons {
+        let mut remaining: Vec<usize> = (0..polygon.vertices.len()).collect();
+        while remaining.len() > 3 {
+            let n = remaining.len();
+            let ear = (1..=n)
+                .map(|i| i % n)
+                .find(|&i| {
+                    let corner = [
+                        remaining[(i + n - 1) % n],
+                        remaining[i],
+                        remaining[(i + 1) % n],
+                    ];
+                    let rest = remaining.iter().filter(|&&j| j != remaining[i]);
+                    !is_collinear(polygon, corner) && !is_all_collinear(polygon, rest)
+                })
+                .expect("Convex polygon has no ear");
+            let corner = [
+                remaining[(ear + n - 1) % n],
+                remaining[ear],
+                remaining[(ear + 1) % n],
+            ];
+            triangles.push(polygon_triangle(polygon, corner));
+            remaining.remove(ear);
+        }
+        triangles.push(polygon_triangle(
+            polygon,
+            [remaining[0], remaining[1], remaining[2]],
+        ));
+    }
+    triangles
+}
+
+fn polygon_triangle(polygon: &Polygon, [a, b, c]: [usize; 3]) -> Triangle {
+    Triangle {
+        vertices: [
+            polygon.vertices[a],
+            polygon.vertices[b],
+            polygon.vertices[c],
+        ],
+        normal: polygon.normal,
+        uvs: [polygon.uvs[a], polygon.uvs[b], polygon.uvs[c]],
+    }
+}
+
+fn is_collinear(polygon: &Polygon, [a, b, c]: [usize; 3]) -> bool {
+    let [a, b, c] = [a, b, c].map(|i| polygon.vertices[i]);
+    (b - a).cross(c - a) == IVec3::ZERO
+}
+
+fn is_all_collinear<'a>(polygon: &Polygon, mut indices: impl Iterator<Item = &'a usize>) -> bool {
+    let (Some(&a), Some(&b)) = (indices.next(), indices.next()) else {
+        return true;
+    };
+    indices.all(|&c| is_collinear(polygon, [a, b, c]))
+}
+
+/// Computes the ambient occlusion factor of each quad corner, ranging from 0
+/// (fully occluded) to 3 (not occluded).
+pub fn get_quad_ao(quad: &Quad, geometry: &Geometry) -> [u8; 4] {
+    let normal = quad.normal.round().as_ivec3();
+    std::array::from_fn(|i| {
+        let vertex = quad.vertices[i];
+        let out1 = (vertex - quad.vertices[(i + 1) % 4]).signum();
+        let out2 = (vertex - quad.vertices[(i + 3) % 4]).signum();
+        let cell = |dir: IVec3| vertex + dir.min(IVec3::ZERO);
+        let side1 = geometry.is_solid(cell(normal + out1 - out2));
+        let side2 = geometry.is_solid(cell(normal - out1 + out2));
+        let corner = geometry.is_solid(cell(normal + out1 + out2));
+        if side1 && side2 {
+            0
+        } else {
+            3 - side1 as u8 - side2 as u8 - corner as u8
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        geometry::{GeometryObject, content_of, convert_to_geometry, cube, generate_quads},
+        tjunction::split_t_junctions,
+    };
+
+    fn geometry_of(obj: GeometryObject) -> Geometry {
+        convert_to_geometry(&content_of(obj)).unwrap()
+    }
+
+    fn assert_outward(geometry: &Geometry, triangles: &[Triangle]) {
+        for triangle in triangles {
+            let [a, b, c] = triangle.vertices.map(|v| v.as_vec3());
+            let cross = (b - a).cross(c - a);
+            assert!(cross.length() > 0.0, "degenerate triangle {:?}", triangle);
+            let normal = cross.normalize();
+            assert!(
+                normal.abs_diff_eq(triangle.normal, 1e-6),
+                "winding does not match normal: {:?}",
+                triangle
+            );
+            let center = (a + b + c) / 3.0;
+            let inside = (center - normal * 0.5).floor().as_ivec3();
+            let outside = (center + normal * 0.5).floor().as_ivec3();
+            assert!(
+                geometry.is_solid(inside),
+                "not facing outward: {:?}",
+                triangle
+            );
+            assert!(
+                !geometry.is_solid(outside),
+                "not facing outward: {:?}",
+                triangle
+            );
+        }
+    }
+
+    fn shapes() -> Vec<GeometryObject> {
+        vec![
+            cube(IVec3::ZERO, IVec3::ONE),
+            cube(IVec3::new(-2, 1, 3), IVec3::new(3, 1, 2)),
+            GeometryObject::Union(
+                Box::new(cube(IVec3::ZERO, IVec3::new(3, 1, 1))),
+                Box::new(cube(IVec3::ZERO, IVec3::new(1, 3, 2))),
+            ),
+            GeometryObject::Minus(
+                Box::new(cube(IVec3::ZERO, IVec3::splat(4))),
+                Box::new(cube(IVec3::ONE, IVec3::new(2, 2, 4))),
+            ),
+        ]
+    }
+
+    #[test]
+    fn triangles_are_wound_outward() {
+        for obj in shapes() {
+            let geometry = geometry_of(obj);
+            let quads = generate_quads(&geometry);
+            for diagonal in [Diagonal::Fixed, Diagonal::Ao] {
+                let triangles = triangulate_quads(&quads, &geometry, diagonal);
+                assert_eq!(triangles.len(), quads.len() * 2);
+                assert_outward(&geometry, &triangles);
+            }
+        }
+    }
+
+    #[test]
+    fn split_polygons_are_wound_outward() {
+        for obj in shapes() {
+            let geometry = geometry_of(obj);
+            let quads = generate_quads(&geometry);
+            let triangles = triangulate_polygons(&split_t_junctions(&quads));
+            assert_outward(&geometry, &triangles);
+
+            // The cross product spans the full quad but twice each triangle.
+            let area = |v: &[IVec3]| (v[1] - v[0]).cross(v[2] - v[0]).abs().element_sum();
+            let quad_area: i32 = quads.iter().map(|q| area(&q.vertices)).sum();
+            let triangle_area: i32 = triangles.iter().map(|t| area(&t.vertices)).sum();
+            assert_eq!(quad_area * 2, triangle_area);
+        }
+    }
+
+    #[test]
+    fn ao_of_isolated_face_is_unoccluded() {
+        let geometry = geometry_of(cube(IVec3::ZERO, IVec3::ONE));
+        for quad in generate_quads(&geometry) {
+            assert_eq!(get_quad_ao(&quad, &geometry), [3; 4]);
+        }
+    }
+
+    #[test]
+    fn ao_diagonal_avoids_occluded_corner() {
+        // A block touching the top face only at one corner occludes exactly
+        // that corner, which should end up in a single triangle.
+        let geometry = geometry_of(GeometryObject::Union(
+            Box::new(cube(IVec3::ZERO, IVec3::ONE)),
+            Box::new(cube(IVec3::ONE, IVec3::ONE)),
+        ));
+        let quads = generate_quads(&geometry);
+        let top = quads
+            .iter()
+            .find(|q| q.normal == Vec3::Y && q.vertices.iter().all(|v| v.y == 1))
+            .unwrap();
+        let ao = get_quad_ao(top, &geometry);
+        let occluded: Vec<_> = (0..4).filter(|&i| ao[i] < 3).collect();
+        assert_eq!(occluded.len(), 1);
+        let vertex = top.vertices[occluded[0]];
+
+        let triangles = triangulate_quads(std::slice::from_ref(top), &geometry, Diagonal::Ao);
+        let containing = triangles
+            .iter()
+            .filter(|t| t.vertices.contains(&vertex))
+            .count();
+        assert_eq!(containing, 1);
+    }
+}
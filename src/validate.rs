@@ -0,0 +1,330 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+use bevy_math::{IVec3, Vec2, Vec3};
+
+use crate::{
+    geometry::Quad,
+    tjunction::{Polygon, count_t_junctions, split_t_junctions},
+};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub degenerate_quads: usize,
+    pub normal_mismatches: usize,
+    pub uvs_out_of_bounds: usize,
+    pub uv_overlaps: usize,
+    pub open_edges: usize,
+    pub non_manifold_edges: usize,
+    pub inconsistent_edges: usize,
+    /// Vertices lying inside an edge of another face. Greedy meshing produces
+    /// these unless they are fixed, so they do not make the mesh invalid.
+    pub t_junctions: usize,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        Self {
+            t_junctions: 0,
+            ..*self
+        } == Self::default()
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "degenerate quads: {}", self.degenerate_quads)?;
+        writeln!(f, "normal mismatches: {}", self.normal_mismatches)?;
+        writeln!(f, "uvs out of bounds: {}", self.uvs_out_of_bounds)?;
+        writeln!(f, "uv overlaps: {}", self.uv_overlaps)?;
+        writeln!(f, "open edges: {}", self.open_edges)?;
+        writeln!(f, "non-manifold edges: {}", self.non_manifold_edges)?;
+        writeln!(f, "inconsistent edges: {}", self.inconsistent_edges)?;
+        write!(f, "t-junctions: {}", self.t_junctions)
+    }
+}
+
+/// A mesh whose polygons share vertex positions through indices.
+#[derive(Debug)]
+pub struct WeldedMesh {
+    pub positions: Vec<IVec3>,
+    pub polygons: Vec<WeldedPolygon>,
+}
+
+#[derive(Debug)]
+pub struct WeldedPolygon {
+    pub indices: Vec<usize>,
+    pub normal: Vec3,
+    pub uvs: Vec<Vec2>,
+}
+
+/// Checks the quads for degenerate geometry, wrong normals and overlapping
+/// uvs, and the mesh they form for open, non-manifold and inconsistently
+/// oriented edges. T-junctions are counted in the mesh as written, that is
+/// after splitting them if `fix_t_junctions` is set.
+pub fn validate_quads(
+    quads: &[Quad],
+    fix_t_junctions: bool,
+    split_non_manifold: bool,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    for quad in quads {
+        let [a, b, c, d] = quad.vertices;
+        if (b - a).cross(c - a) == IVec3::ZERO || (c - a).cross(d - a) == IVec3::ZERO {
+            report.degenerate_quads += 1;
+        } else if !quad
+            .normal
+            .abs_diff_eq(Quad::get_normal(quad.vertices), 1e-5)
+        {
+            report.normal_mismatches += 1;
+        }
+        if quad
+            .uvs
+            .iter()
+            .any(|uv| uv.cmplt(Vec2::ZERO).any() || uv.cmpgt(Vec2::ONE).any())
+        {
+            report.uvs_out_of_bounds += 1;
+        }
+    }
+    report.uv_overlaps = count_uv_overlaps(quads);
+
+    let polygons = split_t_junctions(quads);
+    report.t_junctions = if fix_t_junctions {
+        count_t_junctions(&polygons)
+    } else {
+        count_t_junctions(&quads.iter().map(Polygon::from).collect::<Vec<_>>())
+    };
+
+    let mesh = weld_polygons(&polygons, split_non_manifold);
+    let mut edges: HashMap<(usize, usize), Vec<bool>> = HashMap::new();
+    for polygon in &mesh.polygons {
+        for (a, b) in polygon_edges(&polygon.indices) {
+            edges.entry((a.min(b), a.max(b))).or_default().push(a < b);
+        }
+    }
+    for uses in edges.values() {
+        match uses.len() {
+            1 => report.open_edges += 1,
+            2 if uses[0] == uses[1] => report.inconsistent_edges += 1,
+            2 => {}
+            _ => report.non_manifold_edges += 1,
+        }
+    }
+    report
+}
+
+fn count_uv_overlaps(quads: &[Quad]) -> usize {
+    let rects: Vec<_> = quads
+        .iter()
+        .map(|quad| {
+            let min = quad.uvs.iter().fold(Vec2::MAX, |acc, &uv| acc.min(uv));
+            let max = quad.uvs.iter().fold(Vec2::MIN, |acc, &uv| acc.max(uv));
+            (min, max)
+        })
+        .collect();
+    let mut count = 0;
+    for (i, (min1, max1)) in rects.iter().enumerate() {
+        for (min2, max2) in &rects[i + 1..] {
+            let overlap = max1.min(*max2) - min1.max(*min2);
+            if overlap.cmpgt(Vec2::splat(1e-6)).all() {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// Merges polygon corners at the same position into shared vertices. Edges
+/// shared by more than two polygons, as between voxels touching only along an
+/// edge, can be made manifold by splitting their vertices between the voxels.
+/// The polygons must not contain T-junctions.
+pub fn weld_polygons(polygons: &[Polygon], split_non_manifold: bool) -> WeldedMesh {
+    let offsets: Vec<usize> = polygons
+        .iter()
+        .scan(0, |offset, polygon| {
+            let start = *offset;
+            *offset += polygon.vertices.len();
+            Some(start)
+        })
+        .collect();
+    let corner_count = polygons.iter().map(|p| p.vertices.len()).sum();
+    let mut parents: Vec<usize> = (0..corner_count).collect();
+
+    if split_non_manifold {
+        // Corners are only merged if they are connected through edges shared
+        // by exactly two polygons, or through faces of the same voxel on
+        // edges shared by more.
+        let mut edges: HashMap<[[i32; 3]; 2], Vec<EdgeUse>> = HashMap::new();
+        for (p, polygon) in polygons.iter().enumerate() {
+            let n = polygon.vertices.len();
+            for c in 0..n {
+                let (a, b) = (polygon.vertices[c], polygon.vertices[(c + 1) % n]);
+                let mut key = [a.to_array(), b.to_array()];
+                key.sort();
+                edges.entry(key).or_default().push(EdgeUse {
+                    from: a,
+                    start: offsets[p] + c,
+                    end: offsets[p] + (c + 1) % n,
+                    cell: get_solid_cell(a, b, polygon.normal),
+                });
+            }
+        }
+        for uses in edges.values() {
+            for (i, use1) in uses.iter().enumerate() {
+                for use2 in &uses[i + 1..] {
+                    if uses.len() != 2 && use1.cell != use2.cell {
+                        continue;
+                    }
+                    if use1.from == use2.from {
+                        union(&mut parents, use1.start, use2.start);
+                        union(&mut parents, use1.end, use2.end);
+                    } else {
+                        union(&mut parents, use1.start, use2.end);
+                        union(&mut parents, use1.end, use2.start);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut positions = Vec::new();
+    let mut vertex_ids: HashMap<(IVec3, usize), usize> = HashMap::new();
+    let mut welded = Vec::with_capacity(polygons.len());
+    for (p, polygon) in polygons.iter().enumerate() {
+        let indices = (0..polygon.vertices.len())
+            .map(|c| {
+                let position = polygon.vertices[c];
+                let root = match split_non_manifold {
+                    true => find(&mut parents, offsets[p] + c),
+                    false => 0,
+                };
+                *vertex_ids.entry((position, root)).or_insert_with(|| {
+                    positions.push(position);
+                    positions.len() - 1
+                })
+            })
+            .collect();
+        welded.push(WeldedPolygon {
+            indices,
+            normal: polygon.normal,
+            uvs: polygon.uvs.clone(),
+        });
+    }
+    WeldedMesh {
+        positions,
+        polygons: welded,
+    }
+}
+
+struct EdgeUse {
+    from: IVec3,
+    start: usize,
+    end: usize,
+    cell: IVec3,
+}
+
+/// Returns the solid voxel behind a polygon that touches its edge from `a` to
+/// `b` at the end with the smaller coordinates.
+fn get_solid_cell(a: IVec3, b: IVec3, normal: Vec3) -> IVec3 {
+    let normal = normal.round().as_ivec3();
+    let inward = normal.cross((b - a).signum());
+    // Doubled coordinates of the voxel center, to stay on the integer lattice.
+    let center = 2 * a.min(b) + (b - a).abs().signum() + inward - normal;
+    center.div_euclid(IVec3::splat(2))
+}
+
+fn polygon_edges<T: Copy>(vertices: &[T]) -> impl Iterator<Item = (T, T)> {
+    let n = vertices.len();
+    (0..n).map(move |i| (vertices[i], vertices[(i + 1) % n]))
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[i] = root;
+    root
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    parents[a] = b;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{GeometryObject, cube, textured_quads_of};
+
+    fn diagonal_cubes() -> GeometryObject {
+        GeometryObject::Union(
+            Box::new(cube(IVec3::ZERO, IVec3::ONE)),
+            Box::new(cube(IVec3::new(1, 1, 0), IVec3::ONE)),
+        )
+    }
+
+    #[test]
+    fn generated_meshes_are_valid() {
+        let shapes = [
+            cube(IVec3::ZERO, IVec3::ONE),
+            cube(IVec3::ZERO, IVec3::new(2, 1, 1)),
+            GeometryObject::Minus(
+                Box::new(cube(IVec3::ZERO, IVec3::splat(3))),
+                Box::new(cube(IVec3::new(1, 1, 0), IVec3::new(1, 1, 3))),
+            ),
+        ];
+        for obj in shapes {
+            let report = validate_quads(&textured_quads_of(obj).0, false, false);
+            assert!(report.is_valid(), "{}", report);
+        }
+    }
+
+    #[test]
+    fn diagonal_voxels_are_non_manifold_until_split() {
+        let quads = textured_quads_of(diagonal_cubes()).0;
+        let report = validate_quads(&quads, false, false);
+        assert_eq!(report.non_manifold_edges, 1);
+
+        let report = validate_quads(&quads, true, true);
+        assert!(report.is_valid(), "{}", report);
+    }
+
+    #[test]
+    fn reports_t_junctions_unless_fixed() {
+        // The faces of the block on top end inside the long edges of the slab.
+        let quads = textured_quads_of(GeometryObject::Union(
+            Box::new(cube(IVec3::ZERO, IVec3::new(2, 1, 1))),
+            Box::new(cube(IVec3::new(0, 1, 0), IVec3::ONE)),
+        ))
+        .0;
+        let report = validate_quads(&quads, false, false);
+        assert!(report.t_junctions > 0);
+        assert!(report.is_valid(), "{}", report);
+        assert_eq!(validate_quads(&quads, true, false).t_junctions, 0);
+    }
+
+    #[test]
+    fn splitting_duplicates_shared_edge_vertices() {
+        let quads = textured_quads_of(diagonal_cubes()).0;
+        let polygons = split_t_junctions(&quads);
+        assert_eq!(weld_polygons(&polygons, false).positions.len(), 14);
+        assert_eq!(weld_polygons(&polygons, true).positions.len(), 16);
+    }
+
+    #[test]
+    fn detects_broken_quads() {
+        let mut quads = textured_quads_of(cube(IVec3::ZERO, IVec3::ONE)).0;
+        quads[0].normal = -quads[0].normal;
+        quads[1].uvs = quads[2].uvs;
+        quads[3].uvs[0] = Vec2::new(1.5, 0.0);
+        quads[4].vertices[2] = quads[4].vertices[1];
+        let report = validate_quads(&quads, false, false);
+        assert_eq!(report.normal_mismatches, 1);
+        assert_eq!(report.uv_overlaps, 1);
+        assert_eq!(report.uvs_out_of_bounds, 1);
+        assert_eq!(report.degenerate_quads, 1);
+    }
+}
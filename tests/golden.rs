@@ -0,0 +1,87 @@
+//! Runs the mesher on every model in `models/` and compares the results with
+//! the files in `tests/golden/`. Run with `UPDATE_GOLDEN=1` to bless new
+//! outputs.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const MODELS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/models");
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+fn run_mesher(output: &Path) {
+    if output.exists() {
+        std::fs::remove_dir_all(output).unwrap();
+    }
+    std::fs::create_dir_all(output).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_voxel-mesher"))
+        .args(["--input", MODELS_DIR, "--pattern", "*.txt", "--output"])
+        .arg(output)
+        .status()
+        .unwrap();
+    assert!(status.success(), "voxel-mesher failed with {}", status);
+}
+
+fn output_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for model in std::fs::read_dir(dir).unwrap() {
+        for file in std::fs::read_dir(model.unwrap().path()).unwrap() {
+            let path = file.unwrap().path();
+            files.push(path.strip_prefix(dir).unwrap().to_path_buf());
+        }
+    }
+    files.sort();
+    files
+}
+
+fn assert_same_file(actual: &Path, expected: &Path) {
+    if actual.extension().is_some_and(|ext| ext == "png") {
+        // Compare pixels, as the encoded bytes depend on the png version.
+        let actual_img = image::open(actual).unwrap().into_rgba8();
+        let expected_img = image::open(expected).unwrap().into_rgba8();
+        assert!(
+            actual_img == expected_img,
+            "{} differs from {}",
+            actual.display(),
+            expected.display()
+        );
+    } else {
+        let actual_str = std::fs::read_to_string(actual).unwrap();
+        let expected_str = std::fs::read_to_string(expected).unwrap();
+        assert!(
+            actual_str == expected_str,
+            "{} differs from {}",
+            actual.display(),
+            expected.display()
+        );
+    }
+}
+
+#[test]
+fn models_match_golden_files() {
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    run_mesher(&output);
+    let golden = Path::new(GOLDEN_DIR);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        if golden.exists() {
+            std::fs::remove_dir_all(golden).unwrap();
+        }
+        for file in output_files(&output) {
+            std::fs::create_dir_all(golden.join(&file).parent().unwrap()).unwrap();
+            std::fs::copy(output.join(&file), golden.join(&file)).unwrap();
+        }
+        return;
+    }
+
+    let files = output_files(&output);
+    assert_eq!(
+        files,
+        output_files(golden),
+        "Set of output files changed, run with UPDATE_GOLDEN=1 to bless"
+    );
+    for file in files {
+        assert_same_file(&output.join(&file), &golden.join(&file));
+    }
+}